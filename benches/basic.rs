@@ -15,6 +15,15 @@ const SPHERE_RENDER_SETTINGS: RenderSettings = RenderSettings {
     samples_per_pixel: 5,
     max_depth: 10,
     thread_count: 1,
+    integrator: IntegratorKind::PathTracer,
+    sampler: SamplerKind::Independent,
+    filter: Filter {
+        kind: FilterKind::Box,
+        radius: 0.5,
+    },
+    seed: 0,
+    aovs: Vec::new(),
+    adaptive: None,
 };
 
 fn sphere1() {
@@ -47,6 +56,61 @@ fn sphere3() {
     );
 }
 
+// A grid of many small spheres, to compare `HittableList` against `Bvh`.
+fn many_spheres(use_bvh: bool) -> Scene {
+    let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
+    objects.push(Box::new(Sphere {
+        center: Vec3(0.0, -1000.0, 0.0),
+        radius: 1000.0,
+        material: Box::new(Lambertian {
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+            bump_map: None,
+        }),
+    }));
+
+    for a in -16..16 {
+        for b in -16..16 {
+            let center = Vec3(a as f64 * 0.5 + 0.25, 0.2, b as f64 * 0.5 + 0.25);
+            let material: Box<dyn Material> = if (a + b) % 2 == 0 {
+                Box::new(Lambertian {
+                    albedo: Vec3(0.7, 0.3, 0.2).into(),
+                    bump_map: None,
+                })
+            } else {
+                Box::new(Metal {
+                    albedo: Vec3(0.8, 0.8, 0.8).into(),
+                    fuzz: 0.2,
+                    bump_map: None,
+                })
+            };
+            objects.push(Box::new(Sphere {
+                center,
+                radius: 0.2,
+                material,
+            }));
+        }
+    }
+
+    let root: Box<dyn Hittable> = if use_bvh {
+        Box::new(Bvh::new(objects))
+    } else {
+        Box::new(HittableList { hittables: objects })
+    };
+
+    let forward = Vec3(0.0, -0.4, 1.0).normalized();
+    let up = forward.cross(Vec3(1.0, 0.0, 0.0));
+    let camera = Camera::new(Vec3(0.0, 3.0, -8.0), up, forward, 40.0, 0.0, 8.0);
+
+    Scene {
+        root,
+        camera,
+        background: Background::default(),
+        tone_mapping: None,
+        image_settings: None,
+        render_settings: None,
+    }
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
     let mut group = c.benchmark_group("spheres");
     group.sample_size(20);
@@ -55,6 +119,33 @@ fn criterion_benchmark(c: &mut Criterion) {
     group.bench_function("sphere2", |b| b.iter(sphere2));
     group.bench_function("sphere3", |b| b.iter(sphere3));
     group.finish();
+
+    let list_scene = many_spheres(false);
+    let bvh_scene = many_spheres(true);
+    let mut group = c.benchmark_group("many_spheres");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(25));
+    group.bench_function("list", |b| {
+        b.iter(|| {
+            render(
+                &list_scene,
+                &SPHERE_IMAGE_SETTINGS,
+                &SPHERE_RENDER_SETTINGS,
+                false,
+            )
+        })
+    });
+    group.bench_function("bvh", |b| {
+        b.iter(|| {
+            render(
+                &bvh_scene,
+                &SPHERE_IMAGE_SETTINGS,
+                &SPHERE_RENDER_SETTINGS,
+                false,
+            )
+        })
+    });
+    group.finish();
 }
 
 criterion_group!(benches, criterion_benchmark);
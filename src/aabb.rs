@@ -0,0 +1,91 @@
+use crate::Ray;
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// An axis-aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// An empty box, which is the identity for `surrounding`.
+    pub const EMPTY: Self = Aabb {
+        min: Vec3(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        max: Vec3(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+    };
+
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn surrounding(self, other: Self) -> Self {
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    pub fn including(self, point: Vec3) -> Self {
+        Aabb {
+            min: self.min.min(point),
+            max: self.max.max(point),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn extent(&self) -> Vec3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.extent();
+        if d.0 < 0.0 || d.1 < 0.0 || d.2 < 0.0 {
+            return 0.0;
+        }
+        2.0 * (d.0 * d.1 + d.1 * d.2 + d.2 * d.0)
+    }
+
+    /// The axis (0, 1 or 2) along which the box is the longest.
+    pub fn longest_axis(&self) -> usize {
+        let d = self.extent();
+        if d.0 > d.1 && d.0 > d.2 {
+            0
+        } else if d.1 > d.2 {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Slab test against a ray. `inverse_direction` is the component-wise reciprocal of
+    /// `ray.direction`, which callers testing many boxes against one ray should compute once.
+    pub fn hit(&self, ray: &Ray, inverse_direction: Vec3, t_range: Range<f64>) -> bool {
+        let mut t_min = t_range.start;
+        let mut t_max = t_range.end;
+
+        for axis in 0..3 {
+            let t0 = (self.min[axis] - ray.origin[axis]) * inverse_direction[axis];
+            let t1 = (self.max[axis] - ray.origin[axis]) * inverse_direction[axis];
+            let (t0, t1) = if inverse_direction[axis] < 0.0 {
+                (t1, t0)
+            } else {
+                (t0, t1)
+            };
+
+            // Written so that NaNs (from 0 * inf) do not cause a miss.
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+            if t_max < t_min {
+                return false;
+            }
+        }
+
+        true
+    }
+}
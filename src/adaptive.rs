@@ -0,0 +1,64 @@
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+
+// Dark channels are judged as if they were at least this bright, otherwise pixels that are
+// almost black would need an absurd amount of samples to reach a relative error threshold.
+const MIN_BRIGHTNESS: f64 = 0.05;
+
+/// Adaptive sampling: every pixel gets at least `min_samples` samples, and then keeps being
+/// sampled until the estimated relative error of each of its color channels is below
+/// `threshold`, or it reaches `max_samples`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub max_samples: u32,
+    pub threshold: f64,
+}
+
+impl AdaptiveSampling {
+    pub(crate) fn is_converged(&self, stats: &SampleStats) -> bool {
+        if stats.count < self.min_samples.max(2) {
+            return false;
+        }
+        // The standard error of the mean, relative to the mean.
+        let variance = stats.variance();
+        (0..3).all(|i| {
+            let standard_error = (variance[i] / f64::from(stats.count)).sqrt();
+            standard_error / stats.mean[i].max(MIN_BRIGHTNESS) < self.threshold
+        })
+    }
+}
+
+/// Running mean and variance of the colors of a pixel's samples, from Welford's algorithm.
+pub(crate) struct SampleStats {
+    pub count: u32,
+    mean: Vec3,
+    // Sum of squared differences from the mean.
+    m2: Vec3,
+}
+
+impl SampleStats {
+    pub fn new() -> Self {
+        SampleStats {
+            count: 0,
+            mean: Vec3::ZERO,
+            m2: Vec3::ZERO,
+        }
+    }
+
+    pub fn add(&mut self, color: Vec3) {
+        self.count += 1;
+        let delta = color - self.mean;
+        self.mean += delta / f64::from(self.count);
+        self.m2 += delta * (color - self.mean);
+    }
+
+    /// The sample variance, per channel.
+    pub fn variance(&self) -> Vec3 {
+        if self.count > 1 {
+            self.m2 / f64::from(self.count - 1)
+        } else {
+            Vec3::ZERO
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::adaptive::SampleStats;
+use crate::Hit;
+use crate::Hittable;
+use crate::Ray;
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// Arbitrary output variables: per-pixel information about the scene that `render` can
+/// produce besides the color, e.g. for compositing and denoising.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aov {
+    // Distance from the camera to the nearest hit among the pixel's samples, infinite if none
+    // of them hit anything.
+    Depth,
+    // Average world space shading normal of the first hits, facing the camera.
+    Normal,
+    // Average `Material::albedo` of the first hits.
+    Albedo,
+    // Number of the hittable seen by the pixel's first sample, counting from 1 in the order of
+    // `Hittable::collect_objects`. 0 is the background.
+    ObjectId,
+    // Like the object ID, but hashed from the material's parameters, so hittables with equal
+    // materials share an ID.
+    MaterialId,
+    // Sample variance of the color.
+    Variance,
+    // How many samples the pixel got, which only differs between pixels with adaptive
+    // sampling.
+    SampleCount,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::ObjectId,
+        Aov::MaterialId,
+        Aov::Variance,
+        Aov::SampleCount,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+            Aov::Variance => "variance",
+            Aov::SampleCount => "samples",
+        }
+    }
+
+    /// Names of the meaningful components of the AOV's pixels. Single channel AOVs store their
+    /// value in all three components.
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::Albedo | Aov::Variance => &["R", "G", "B"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+            Aov::SampleCount => &["count"],
+        }
+    }
+
+    // Whether the AOV needs the first hit of the camera rays.
+    fn needs_hit(self) -> bool {
+        self != Aov::Variance && self != Aov::SampleCount
+    }
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Aov::ALL
+            .iter()
+            .copied()
+            .find(|aov| aov.name() == s)
+            .ok_or_else(|| {
+                format!(
+                    "unknown AOV '{}', expected depth, normal, albedo, object_id, material_id, \
+                     variance or samples",
+                    s
+                )
+            })
+    }
+}
+
+/// Object IDs for all hittables of a scene, by address.
+pub(crate) struct ObjectIds(HashMap<usize, u32>);
+
+impl ObjectIds {
+    pub fn new(root: &dyn Hittable) -> Self {
+        let mut objects = Vec::new();
+        root.collect_objects(&mut objects);
+        let ids = objects
+            .into_iter()
+            .enumerate()
+            .map(|(i, object)| (address(object), i as u32 + 1))
+            .collect();
+        ObjectIds(ids)
+    }
+
+    fn get(&self, object: &dyn Hittable) -> u32 {
+        self.0.get(&address(object)).copied().unwrap_or(0)
+    }
+}
+
+fn address<T: ?Sized>(value: &T) -> usize {
+    value as *const T as *const () as usize
+}
+
+// The material ID of a material, from an FNV-1a hash of its serialized form. Cut down to 24 bits
+// so it is still exact in 32-bit float outputs.
+fn material_id(hit: &Hit) -> u32 {
+    let serialized = ron::to_string(&hit.material).unwrap_or_default();
+    let hash = serialized.bytes().fold(0x811c_9dc5u32, |hash, byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    });
+    (hash & 0xff_ffff).max(1)
+}
+
+/// Collects the AOVs of one pixel over its samples.
+pub(crate) struct AovPixel<'a> {
+    aovs: &'a [Aov],
+    needs_hit: bool,
+    object_ids: &'a ObjectIds,
+    // Serializing materials for their IDs is slow, so it is only done once per material (and
+    // per tile).
+    material_ids: &'a mut HashMap<usize, u32>,
+    samples: u32,
+    depth: f64,
+    normal: Vec3,
+    albedo: Vec3,
+    object_id: u32,
+    material_id: u32,
+}
+
+impl<'a> AovPixel<'a> {
+    pub fn new(
+        aovs: &'a [Aov],
+        object_ids: &'a ObjectIds,
+        material_ids: &'a mut HashMap<usize, u32>,
+    ) -> Self {
+        AovPixel {
+            aovs,
+            needs_hit: aovs.iter().any(|aov| aov.needs_hit()),
+            object_ids,
+            material_ids,
+            samples: 0,
+            depth: f64::INFINITY,
+            normal: Vec3::ZERO,
+            albedo: Vec3::ZERO,
+            object_id: 0,
+            material_id: 0,
+        }
+    }
+
+    /// Adds a sample with camera ray `ray`, which hit the scene at `hit`.
+    pub fn add_sample(&mut self, ray: Ray, hit: Option<&Hit>) {
+        self.samples += 1;
+        if !self.needs_hit {
+            return;
+        }
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return,
+        };
+
+        self.depth = self.depth.min(hit.t * ray.direction.mag());
+        self.normal += hit.normal;
+        self.albedo += hit.material.albedo(hit);
+        if self.samples == 1 {
+            self.object_id = self.object_ids.get(hit.object);
+            self.material_id = *self
+                .material_ids
+                .entry(address(hit.material))
+                .or_insert_with(|| material_id(hit));
+        }
+    }
+
+    /// The final values of the requested AOVs, in the same order. `stats` are those of the
+    /// pixel's colors.
+    pub fn finish<'b>(&'b self, stats: &'b SampleStats) -> impl Iterator<Item = Vec3> + 'b {
+        let samples = f64::from(self.samples.max(1));
+        self.aovs.iter().map(move |aov| match aov {
+            Aov::Depth => Vec3(self.depth, self.depth, self.depth),
+            Aov::Normal => self.normal / samples,
+            Aov::Albedo => self.albedo / samples,
+            Aov::ObjectId => {
+                let id = f64::from(self.object_id);
+                Vec3(id, id, id)
+            }
+            Aov::MaterialId => {
+                let id = f64::from(self.material_id);
+                Vec3(id, id, id)
+            }
+            Aov::Variance => stats.variance(),
+            Aov::SampleCount => {
+                let count = f64::from(stats.count);
+                Vec3(count, count, count)
+            }
+        })
+    }
+}
@@ -0,0 +1,96 @@
+use crate::clamp;
+use crate::hdr::HdrImage;
+use crate::Ray;
+use crate::Vec3;
+use crate::{canonical_path, resolve_scene_path, serialize_scene_path};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// What a ray that does not hit anything in the scene sees.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Background {
+    Solid(Vec3),
+    // Blends from `bottom` (for directions opposite to `axis`) to `top` (along `axis`).
+    Gradient { bottom: Vec3, top: Vec3, axis: Vec3 },
+    Environment(EnvironmentMap),
+}
+
+impl Default for Background {
+    // A simple sky.
+    fn default() -> Self {
+        Background::Gradient {
+            bottom: Vec3::ONE,
+            top: Vec3(0.5, 0.7, 1.0),
+            axis: Vec3(0.0, 1.0, 0.0),
+        }
+    }
+}
+
+impl Background {
+    pub fn color(&self, ray: &Ray) -> Vec3 {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { bottom, top, axis } => {
+                let t = 0.5 * (ray.direction.normalized().dot(axis.normalized()) + 1.0);
+                (1.0 - t) * *bottom + t * *top
+            }
+            Background::Environment(map) => map.color(ray.direction),
+        }
+    }
+}
+
+/// An equirectangular (latitude-longitude) environment map, loaded from a `.hdr` or `.pfm`
+/// file. The scene file only stores the path (relative to the scene file, while `new` takes it
+/// relative to the current directory), the image is loaded on deserialization.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(try_from = "EnvironmentMapData")]
+pub struct EnvironmentMap {
+    #[serde(serialize_with = "serialize_scene_path")]
+    path: PathBuf,
+    // Around the y axis, in degrees.
+    rotation: f64,
+    #[serde(skip)]
+    image: Arc<HdrImage>,
+}
+
+#[derive(Deserialize)]
+struct EnvironmentMapData {
+    path: PathBuf,
+    #[serde(default)]
+    rotation: f64,
+}
+
+impl TryFrom<EnvironmentMapData> for EnvironmentMap {
+    type Error = String;
+
+    fn try_from(data: EnvironmentMapData) -> Result<Self, Self::Error> {
+        let path = resolve_scene_path(&data.path);
+        EnvironmentMap::new(path.clone(), data.rotation)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl EnvironmentMap {
+    pub fn new(path: PathBuf, rotation: f64) -> io::Result<Self> {
+        let image = HdrImage::load(&path)?;
+        Ok(EnvironmentMap {
+            path: canonical_path(&path)?,
+            rotation,
+            image: Arc::new(image),
+        })
+    }
+
+    // The center of the image is in the +z direction, the top row straight up along +y.
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.normalized();
+        let phi = direction.0.atan2(direction.2) + self.rotation.to_radians();
+        let theta = clamp(direction.1, -1.0, 1.0).acos();
+
+        let u = (phi / (2.0 * std::f64::consts::PI) + 0.5).rem_euclid(1.0);
+        let v = theta / std::f64::consts::PI;
+        self.image.sample(u, v)
+    }
+}
@@ -0,0 +1,81 @@
+use crate::texture;
+use crate::Hit;
+use crate::Texture;
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+
+/// Perturbs the shading normal of a material's surfaces, to add detail that the geometry does
+/// not have. Both kinds follow the surface's texture coordinates.
+#[derive(Serialize, Deserialize)]
+pub enum BumpMap {
+    // A tangent space normal map: the color channels, mapped from [0, 1] to [-1, 1], are the
+    // normal along the tangent, the bitangent and the unperturbed normal. Green points towards
+    // increasing v, like in OpenGL. Image textures for normal maps need `srgb: false`.
+    Normal {
+        #[serde(deserialize_with = "texture::deserialize")]
+        map: Box<dyn Texture>,
+    },
+    // A height map: the surface is shaded as if moved along its normal by the brightness of
+    // `map` times `scale` (in world units).
+    Height {
+        #[serde(deserialize_with = "texture::deserialize")]
+        map: Box<dyn Texture>,
+        scale: f64,
+    },
+}
+
+impl BumpMap {
+    /// The perturbed shading normal at `hit`, on the same side as `hit.normal`.
+    pub fn normal(&self, hit: &Hit) -> Vec3 {
+        match self {
+            BumpMap::Normal { map } => {
+                let Vec3(x, y, z) = 2.0 * map.value(hit) - 1.0;
+                (x * hit.tangent + y * hit.bitangent + z * hit.normal).normalized()
+            }
+            BumpMap::Height { map, scale } => {
+                let height = |hit: &Hit| scale * map.value(hit).luminance();
+                let base = height(hit);
+                let (u, v) = hit.uv;
+
+                // Finite differences over about the hit's footprint, or a small fixed step if
+                // it is unknown.
+                let step = |dp: Vec3| {
+                    let step = 0.5 * hit.footprint / dp.mag();
+                    if step > 0.0 && step.is_finite() {
+                        step
+                    } else {
+                        0.0005
+                    }
+                };
+                let du = step(hit.dpdu);
+                let dv = step(hit.dpdv);
+                let shifted_u = Hit {
+                    point: hit.point + du * hit.dpdu,
+                    uv: (u + du, v),
+                    ..*hit
+                };
+                let shifted_v = Hit {
+                    point: hit.point + dv * hit.dpdv,
+                    uv: (u, v + dv),
+                    ..*hit
+                };
+
+                // The derivatives of the displaced surface, leaving out the change of the normal
+                // itself. They are projected onto the shading normal's tangent plane first, so
+                // that interpolated normals still look smooth.
+                let n = hit.normal;
+                let dpdu = hit.dpdu - n * n.dot(hit.dpdu);
+                let dpdv = hit.dpdv - n * n.dot(hit.dpdv);
+                let dpdu = dpdu + (height(&shifted_u) - base) / du * n;
+                let dpdv = dpdv + (height(&shifted_v) - base) / dv * n;
+
+                let normal = dpdu.cross(dpdv).normalized();
+                if normal.dot(n) < 0.0 {
+                    -normal
+                } else {
+                    normal
+                }
+            }
+        }
+    }
+}
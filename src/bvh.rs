@@ -0,0 +1,318 @@
+use crate::Aabb;
+use crate::Hit;
+use crate::Hittable;
+use crate::Ray;
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+const MAX_LEAF_SIZE: usize = 4;
+const BIN_COUNT: usize = 12;
+
+#[derive(Copy, Clone)]
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    // The first child always directly follows its parent in `BvhTree::nodes`.
+    Interior { second_child: usize, axis: usize },
+}
+
+#[derive(Copy, Clone)]
+struct Node {
+    bbox: Aabb,
+    kind: NodeKind,
+}
+
+/// A bounding volume hierarchy over primitives identified by their index.
+///
+/// This only stores the tree itself; the owner keeps the primitives, ordered as returned by
+/// `BvhTree::build`, and tests them through the closure passed to `BvhTree::hit`. That way the
+/// same code accelerates both `Bvh` (over boxed hittables) and triangle meshes (over triangle
+/// indices).
+#[derive(Default)]
+pub(crate) struct BvhTree {
+    nodes: Vec<Node>,
+}
+
+impl BvhTree {
+    /// Builds a tree over primitives with the given bounding boxes, using the surface area
+    /// heuristic. Returns the tree and the order in which the primitives have to be stored, i.e.
+    /// the primitive at position `i` used to be the primitive `order[i]`.
+    pub fn build(bboxes: &[Aabb]) -> (Self, Vec<usize>) {
+        let mut order = (0..bboxes.len()).collect::<Vec<_>>();
+        let centroids = bboxes.iter().map(Aabb::centroid).collect::<Vec<_>>();
+        let mut nodes = Vec::new();
+
+        if !bboxes.is_empty() {
+            build_node(bboxes, &centroids, &mut order, 0, &mut nodes);
+        }
+
+        (BvhTree { nodes }, order)
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.nodes.first().map(|node| node.bbox)
+    }
+
+    pub fn hit<'a, F>(&self, ray: Ray, t_range: Range<f64>, mut hit_primitive: F) -> Option<Hit<'a>>
+    where
+        F: FnMut(usize, Range<f64>) -> Option<Hit<'a>>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let inverse_direction = Vec3(
+            1.0 / ray.direction.0,
+            1.0 / ray.direction.1,
+            1.0 / ray.direction.2,
+        );
+        let mut t_range = t_range;
+        let mut closest = None;
+        self.hit_node(
+            0,
+            &ray,
+            inverse_direction,
+            &mut t_range,
+            &mut closest,
+            &mut hit_primitive,
+        );
+        closest
+    }
+
+    fn hit_node<'a, F>(
+        &self,
+        index: usize,
+        ray: &Ray,
+        inverse_direction: Vec3,
+        t_range: &mut Range<f64>,
+        closest: &mut Option<Hit<'a>>,
+        hit_primitive: &mut F,
+    ) where
+        F: FnMut(usize, Range<f64>) -> Option<Hit<'a>>,
+    {
+        let node = &self.nodes[index];
+        if !node.bbox.hit(ray, inverse_direction, t_range.clone()) {
+            return;
+        }
+
+        match node.kind {
+            NodeKind::Leaf { first, count } => {
+                for i in first..first + count {
+                    if let Some(hit) = hit_primitive(i, t_range.clone()) {
+                        t_range.end = hit.t;
+                        *closest = Some(hit);
+                    }
+                }
+            }
+            NodeKind::Interior { second_child, axis } => {
+                // Visit the child closer to the ray origin first, so that the farther one can
+                // often be skipped entirely after `t_range` shrinks.
+                let (near, far) = if ray.direction[axis] < 0.0 {
+                    (second_child, index + 1)
+                } else {
+                    (index + 1, second_child)
+                };
+                self.hit_node(
+                    near,
+                    ray,
+                    inverse_direction,
+                    t_range,
+                    closest,
+                    hit_primitive,
+                );
+                self.hit_node(far, ray, inverse_direction, t_range, closest, hit_primitive);
+            }
+        }
+    }
+}
+
+fn build_node(
+    bboxes: &[Aabb],
+    centroids: &[Vec3],
+    order: &mut [usize],
+    first: usize,
+    nodes: &mut Vec<Node>,
+) -> usize {
+    let bbox = order
+        .iter()
+        .fold(Aabb::EMPTY, |bbox, &i| bbox.surrounding(bboxes[i]));
+    let node_index = nodes.len();
+    nodes.push(Node {
+        bbox,
+        kind: NodeKind::Leaf {
+            first,
+            count: order.len(),
+        },
+    });
+
+    if order.len() <= MAX_LEAF_SIZE {
+        return node_index;
+    }
+
+    let centroid_bounds = order
+        .iter()
+        .fold(Aabb::EMPTY, |bounds, &i| bounds.including(centroids[i]));
+    let axis = centroid_bounds.longest_axis();
+    let axis_min = centroid_bounds.min[axis];
+    let axis_extent = centroid_bounds.extent()[axis];
+    if axis_extent <= 0.0 {
+        // All centroids coincide, no split is going to separate them.
+        return node_index;
+    }
+
+    let bin_of = |i: usize| {
+        let relative = (centroids[i][axis] - axis_min) / axis_extent;
+        ((relative * BIN_COUNT as f64) as usize).min(BIN_COUNT - 1)
+    };
+
+    let mut bins = [(Aabb::EMPTY, 0usize); BIN_COUNT];
+    for &i in order.iter() {
+        let bin = &mut bins[bin_of(i)];
+        bin.0 = bin.0.surrounding(bboxes[i]);
+        bin.1 += 1;
+    }
+
+    // Find the split (after bin `split`) with the lowest surface area heuristic cost.
+    let mut best_split = 0;
+    let mut best_cost = f64::INFINITY;
+    for split in 0..BIN_COUNT - 1 {
+        let (left_box, left_count) = bins[..=split].iter().fold((Aabb::EMPTY, 0), |(b, c), bin| {
+            (b.surrounding(bin.0), c + bin.1)
+        });
+        let (right_box, right_count) = bins[split + 1..]
+            .iter()
+            .fold((Aabb::EMPTY, 0), |(b, c), bin| {
+                (b.surrounding(bin.0), c + bin.1)
+            });
+        if left_count == 0 || right_count == 0 {
+            continue;
+        }
+
+        let cost = left_box.surface_area() * left_count as f64
+            + right_box.surface_area() * right_count as f64;
+        if cost < best_cost {
+            best_cost = cost;
+            best_split = split;
+        }
+    }
+
+    let mid = partition(order, |i| bin_of(i) <= best_split);
+    let (left, right) = order.split_at_mut(mid);
+    build_node(bboxes, centroids, left, first, nodes);
+    let second_child = build_node(bboxes, centroids, right, first + mid, nodes);
+    nodes[node_index].kind = NodeKind::Interior { second_child, axis };
+
+    node_index
+}
+
+/// Moves all elements for which `predicate` is true to the front, returning how many there are.
+fn partition(slice: &mut [usize], predicate: impl Fn(usize) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..slice.len() {
+        if predicate(slice[i]) {
+            slice.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+/// A bounding volume hierarchy over a set of hittables, as a faster replacement for a
+/// `HittableList` with many children.
+///
+/// Only the hittables themselves are serialized, the tree is rebuilt on deserialization.
+#[derive(Serialize, Deserialize)]
+#[serde(from = "BvhData")]
+pub struct Bvh {
+    hittables: Vec<Box<dyn Hittable>>,
+    #[serde(skip)]
+    tree: BvhTree,
+    // Hittables without a bounding box (e.g. infinite planes) are stored after the ones in the
+    // tree and always tested.
+    #[serde(skip)]
+    bounded_count: usize,
+}
+
+#[derive(Deserialize)]
+struct BvhData {
+    hittables: Vec<Box<dyn Hittable>>,
+}
+
+impl From<BvhData> for Bvh {
+    fn from(data: BvhData) -> Self {
+        Bvh::new(data.hittables)
+    }
+}
+
+impl Bvh {
+    pub fn new(hittables: Vec<Box<dyn Hittable>>) -> Self {
+        let mut bboxes = Vec::with_capacity(hittables.len());
+        let mut bounded = Vec::with_capacity(hittables.len());
+        let mut unbounded = Vec::new();
+        for hittable in hittables.into_iter() {
+            match hittable.bounding_box() {
+                Some(bbox) => {
+                    bboxes.push(bbox);
+                    bounded.push(Some(hittable));
+                }
+                None => unbounded.push(hittable),
+            }
+        }
+
+        let (tree, order) = BvhTree::build(&bboxes);
+        let bounded_count = bounded.len();
+        let mut hittables = order
+            .into_iter()
+            .map(|i| bounded[i].take().unwrap())
+            .collect::<Vec<_>>();
+        hittables.extend(unbounded);
+
+        Bvh {
+            hittables,
+            tree,
+            bounded_count,
+        }
+    }
+}
+
+#[typetag::serde]
+impl Hittable for Bvh {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<Hit<'_>> {
+        let mut t_range = t_range;
+        let mut current_hit = self.tree.hit(ray, t_range.clone(), |i, t_range| {
+            self.hittables[i].hit(ray, t_range)
+        });
+        if let Some(hit) = &current_hit {
+            t_range.end = hit.t;
+        }
+
+        for hittable in self.hittables[self.bounded_count..].iter() {
+            if let Some(new_hit) = hittable.hit(ray, t_range.clone()) {
+                t_range.end = new_hit.t;
+                current_hit = Some(new_hit);
+            }
+        }
+
+        current_hit
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.bounded_count < self.hittables.len() {
+            None
+        } else {
+            self.tree.bounding_box()
+        }
+    }
+
+    fn collect_objects<'a>(&'a self, objects: &mut Vec<&'a dyn Hittable>) {
+        for hittable in self.hittables.iter() {
+            hittable.collect_objects(objects);
+        }
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<&'a dyn Hittable>) {
+        for hittable in self.hittables.iter() {
+            hittable.collect_lights(lights);
+        }
+    }
+}
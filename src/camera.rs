@@ -1,8 +1,9 @@
 use crate::Ray;
+use crate::Sampler;
 use crate::Vec3;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Camera {
     origin: Vec3,
     lower_left_corner: Vec3,
@@ -15,19 +16,20 @@ pub struct Camera {
 }
 
 impl Camera {
+    /// A camera with a square viewport. `render` stretches it to the aspect ratio of the image,
+    /// see `set_aspect_ratio`.
     pub fn new(
         origin: Vec3,
         up: Vec3,
         forward: Vec3,
         vfov: f64, // in degrees
-        aspect_ratio: f64,
         aperture: f64,
         focus_distance: f64,
     ) -> Self {
         let theta = vfov * std::f64::consts::PI / 180.0;
         let h = (theta / 2.0).tan();
         let viewport_height = 2.0 * h;
-        let viewport_width = aspect_ratio * viewport_height;
+        let viewport_width = viewport_height;
 
         let up = up.normalized();
         let forward = forward.normalized();
@@ -48,8 +50,23 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, u: f64, v: f64) -> Ray {
-        let rd = self.lens_radius * Vec3::random_in_unit_disk();
+    /// Makes the viewport `aspect_ratio` times as wide as it is high, keeping its height and
+    /// center.
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        let center = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+        self.horizontal = aspect_ratio * self.vertical.mag() * self.horizontal.normalized();
+        self.lower_left_corner = center - 0.5 * self.horizontal - 0.5 * self.vertical;
+    }
+
+    /// The angle a single pixel covers (vertically) for an image `image_height` pixels high.
+    pub fn pixel_spread(&self, image_height: u32) -> f64 {
+        let center = self.lower_left_corner + 0.5 * self.horizontal + 0.5 * self.vertical;
+        let focus_distance = (center - self.origin).mag();
+        2.0 * (0.5 * self.vertical.mag() / focus_distance).atan() / f64::from(image_height)
+    }
+
+    pub fn get_ray(&self, u: f64, v: f64, sampler: &mut dyn Sampler) -> Ray {
+        let rd = self.lens_radius * Vec3::sample_in_unit_disk(sampler.get_2d());
         let offset = self.right * rd.0 + self.up * rd.1;
 
         Ray::new(
@@ -0,0 +1,134 @@
+use crate::Aov;
+use crate::Film;
+use crate::Vec3;
+
+// Number of filter passes, each one with twice the step size of the previous one. The last pass
+// reaches 2 * 2^4 = 32 pixels away.
+const ITERATIONS: u32 = 5;
+
+// The B3 spline that the 5x5 filter kernel is made of.
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// How different the (compressed) colors, normals, albedos and relative depths of two pixels may
+// be before they stop contributing to each other. The color tolerance halves with every pass.
+const SIGMA_COLOR: f64 = 0.6;
+const SIGMA_NORMAL: f64 = 0.3;
+const SIGMA_ALBEDO: f64 = 0.1;
+const SIGMA_DEPTH: f64 = 0.05;
+
+// Albedos below this are treated as black, and not divided out of the color.
+const MIN_ALBEDO: f64 = 0.01;
+
+/// The AOVs `denoise` uses to find edges in the image. Without them, it still works, but blurs
+/// more.
+pub const DENOISE_AOVS: [Aov; 3] = [Aov::Normal, Aov::Albedo, Aov::Depth];
+
+/// Removes noise from the (linear) colors of the film with the edge-avoiding À-trous wavelet
+/// transform from "Edge-Avoiding À-Trous Wavelet Transform for fast Global Illumination
+/// Filtering" (Dammertz et al., 2010), guided by the film's normal, albedo and depth AOVs.
+///
+/// The albedo is divided out of the colors before filtering and multiplied back in afterwards,
+/// so texture detail does not get blurred together with the noise.
+pub fn denoise(film: &mut Film) {
+    let width = film.width as usize;
+    let height = film.height as usize;
+    let normals = film.aov(Aov::Normal);
+    let depths = film.aov(Aov::Depth);
+    let albedos = film.aov(Aov::Albedo);
+
+    // What the colors get divided by before filtering.
+    let factors = match albedos {
+        Some(albedos) => albedos
+            .iter()
+            .map(|albedo| albedo.map(|a| if a > MIN_ALBEDO { a } else { 1.0 }))
+            .collect(),
+        None => vec![Vec3::ONE; film.pixels.len()],
+    };
+
+    let mut current = film
+        .pixels
+        .iter()
+        .zip(factors.iter())
+        .map(|(&color, &factor)| divide(color, factor))
+        .collect::<Vec<_>>();
+    let mut next = current.clone();
+
+    for iteration in 0..ITERATIONS {
+        let step = 1 << iteration;
+        let sigma_color = SIGMA_COLOR / f64::from(1 << iteration);
+
+        for y in 0..height {
+            for x in 0..width {
+                let p = y * width + x;
+                let color_p = compress(current[p]);
+
+                let mut sum = Vec3::ZERO;
+                let mut weight_sum = 0.0;
+                for (dy, ky) in KERNEL.iter().enumerate() {
+                    let qy = y as isize + (dy as isize - 2) * step;
+                    if qy < 0 || qy >= height as isize {
+                        continue;
+                    }
+                    for (dx, kx) in KERNEL.iter().enumerate() {
+                        let qx = x as isize + (dx as isize - 2) * step;
+                        if qx < 0 || qx >= width as isize {
+                            continue;
+                        }
+                        let q = qy as usize * width + qx as usize;
+
+                        let mut exponent =
+                            (color_p - compress(current[q])).mag_squared() / (sigma_color.powi(2));
+                        if let Some(normals) = normals {
+                            exponent +=
+                                (normals[p] - normals[q]).mag_squared() / SIGMA_NORMAL.powi(2);
+                        }
+                        if let Some(albedos) = albedos {
+                            exponent +=
+                                (albedos[p] - albedos[q]).mag_squared() / SIGMA_ALBEDO.powi(2);
+                        }
+                        if let Some(depths) = depths {
+                            exponent += depth_difference(depths[p].0, depths[q].0) / SIGMA_DEPTH;
+                        }
+
+                        let weight = kx * ky * (-exponent).exp();
+                        sum += weight * current[q];
+                        weight_sum += weight;
+                    }
+                }
+
+                // The center pixel always has a weight of at least 9/64, so this never divides
+                // by zero.
+                next[p] = sum / weight_sum;
+            }
+        }
+
+        std::mem::swap(&mut current, &mut next);
+    }
+
+    for ((pixel, color), factor) in film.pixels.iter_mut().zip(current).zip(factors) {
+        *pixel = color * factor;
+    }
+}
+
+fn divide(a: Vec3, b: Vec3) -> Vec3 {
+    Vec3(a.0 / b.0, a.1 / b.1, a.2 / b.2)
+}
+
+// Maps colors into [0, 1), so the same color tolerance works for dark and very bright regions.
+fn compress(color: Vec3) -> Vec3 {
+    color.map(|c| c.max(0.0) / (1.0 + c.max(0.0)))
+}
+
+// Relative difference between two depths, with infinite depths (the background) only being
+// similar to each other.
+fn depth_difference(a: f64, b: f64) -> f64 {
+    if a.is_infinite() || b.is_infinite() {
+        if a == b {
+            0.0
+        } else {
+            f64::INFINITY
+        }
+    } else {
+        (a - b).abs() / a.min(b).max(1e-6)
+    }
+}
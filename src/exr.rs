@@ -0,0 +1,201 @@
+use crate::Vec3;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// How the channels of an OpenEXR file are stored.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ExrPixelType {
+    Half,
+    Float,
+}
+
+impl ExrPixelType {
+    // The values used in the channel list.
+    fn id(self) -> i32 {
+        match self {
+            ExrPixelType::Half => 1,
+            ExrPixelType::Float => 2,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            ExrPixelType::Half => 2,
+            ExrPixelType::Float => 4,
+        }
+    }
+}
+
+/// A named image that becomes the `<name>.<channel>` channels of an EXR file, e.g. `albedo.R`,
+/// `albedo.G` and `albedo.B`. The channels take the pixels' components in order, so there can
+/// be at most three. The layer with an empty name is the main image, with plain channel names.
+pub struct ExrLayer<'a> {
+    pub name: &'a str,
+    pub channels: &'a [&'a str],
+    pub pixels: &'a [Vec3],
+}
+
+// A single channel of the file, which is what EXR actually stores.
+struct Channel<'a> {
+    name: String,
+    pixels: &'a [Vec3],
+    component: usize,
+}
+
+/// Writes an uncompressed scanline OpenEXR file with the given layers, which all have to be
+/// `width` * `height` pixels, row by row from the top.
+pub fn write_exr(
+    path: &Path,
+    width: u32,
+    height: u32,
+    layers: &[ExrLayer],
+    pixel_type: ExrPixelType,
+) -> io::Result<()> {
+    let mut channels = Vec::new();
+    for layer in layers {
+        assert_eq!(layer.pixels.len(), (width * height) as usize);
+        assert!(layer.channels.len() <= 3);
+        for (component, suffix) in layer.channels.iter().enumerate() {
+            let name = if layer.name.is_empty() {
+                suffix.to_string()
+            } else {
+                format!("{}.{}", layer.name, suffix)
+            };
+            channels.push(Channel {
+                name,
+                pixels: layer.pixels,
+                component,
+            });
+        }
+    }
+    // The format requires the channels sorted by name, and stores each scanline's values in
+    // the same order.
+    channels.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut out = BufWriter::new(File::create(path)?);
+
+    // Magic number and version 2, with no flags set (a single part scanline image).
+    out.write_all(&20000630i32.to_le_bytes())?;
+    out.write_all(&2i32.to_le_bytes())?;
+
+    let mut channel_list = Vec::new();
+    for channel in &channels {
+        channel_list.extend_from_slice(channel.name.as_bytes());
+        channel_list.push(0);
+        channel_list.extend_from_slice(&pixel_type.id().to_le_bytes());
+        // pLinear and three reserved bytes, then the x and y sampling.
+        channel_list.extend_from_slice(&[0; 4]);
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+        channel_list.extend_from_slice(&1i32.to_le_bytes());
+    }
+    channel_list.push(0);
+
+    let mut window = Vec::new();
+    for value in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        window.extend_from_slice(&value.to_le_bytes());
+    }
+
+    let mut header = Vec::new();
+    write_attribute(&mut header, "channels", "chlist", &channel_list);
+    // No compression.
+    write_attribute(&mut header, "compression", "compression", &[0]);
+    write_attribute(&mut header, "dataWindow", "box2i", &window);
+    write_attribute(&mut header, "displayWindow", "box2i", &window);
+    // Increasing y.
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    write_attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    write_attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+    out.write_all(&header)?;
+
+    // Every scanline is its own chunk, starting with its y coordinate and the size of the data.
+    // The offset table in front of them points to each chunk, from the start of the file.
+    let line_size = width as usize * channels.len() * pixel_type.size();
+    let chunk_size = 8 + line_size;
+    let first_chunk = 8 + header.len() + 8 * height as usize;
+    for y in 0..height as usize {
+        out.write_all(&((first_chunk + y * chunk_size) as u64).to_le_bytes())?;
+    }
+
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height as usize {
+        line.clear();
+        for channel in &channels {
+            let row = &channel.pixels[y * width as usize..(y + 1) * width as usize];
+            for pixel in row {
+                let value = pixel[channel.component] as f32;
+                match pixel_type {
+                    ExrPixelType::Half => line.extend_from_slice(&f32_to_f16(value).to_le_bytes()),
+                    ExrPixelType::Float => line.extend_from_slice(&value.to_le_bytes()),
+                }
+            }
+        }
+        out.write_all(&(y as i32).to_le_bytes())?;
+        out.write_all(&(line.len() as i32).to_le_bytes())?;
+        out.write_all(&line)?;
+    }
+
+    out.flush()
+}
+
+fn write_attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+// Converts to an IEEE 754 half precision float, rounding to the nearest representable value.
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity stays infinity, NaN stays NaN.
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        // Too large, becomes infinity.
+        return sign | 0x7c00;
+    }
+
+    // The bits of the result without the sign, and the bits that get shifted out of it.
+    let (half, rest, shift) = if exponent > 0 {
+        (
+            (exponent as u32) << 10 | mantissa >> 13,
+            mantissa & 0x1fff,
+            13,
+        )
+    } else if exponent >= -10 {
+        // Subnormal, with the implicit leading one of the mantissa made explicit.
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        (mantissa >> shift, mantissa & ((1 << shift) - 1), shift)
+    } else {
+        return sign;
+    };
+
+    // Round to nearest, ties to even. A carry out of the mantissa correctly increments the
+    // exponent (or turns the largest values into infinity).
+    let halfway = 1 << (shift - 1);
+    let round_up = rest > halfway || (rest == halfway && half & 1 == 1);
+    sign | (half + round_up as u32) as u16
+}
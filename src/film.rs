@@ -0,0 +1,58 @@
+use crate::Aov;
+use crate::Vec3;
+
+/// The linear radiance arriving at each pixel of the image, stored row by row starting at the
+/// top left. Values are not clamped, so they can go far above 1.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3>,
+    // The requested AOVs, laid out like `pixels`.
+    pub aovs: Vec<(Aov, Vec<Vec3>)>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, aovs: &[Aov]) -> Self {
+        let size = (width * height) as usize;
+        Film {
+            width,
+            height,
+            pixels: vec![Vec3::ZERO; size],
+            aovs: aovs
+                .iter()
+                .map(|&aov| (aov, vec![Vec3::ZERO; size]))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Vec3 {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, color: Vec3) {
+        self.pixels[(y * self.width + x) as usize] = color;
+    }
+
+    pub fn aov(&self, aov: Aov) -> Option<&[Vec3]> {
+        self.aovs
+            .iter()
+            .find(|(a, _)| *a == aov)
+            .map(|(_, pixels)| pixels.as_slice())
+    }
+
+    // Copies all of `other`, which has to have the same AOVs, to `x`, `y` of this film.
+    pub(crate) fn copy_from(&mut self, other: &Film, x: u32, y: u32) {
+        let width = self.width as usize;
+        let copy = |to: &mut [Vec3], from: &[Vec3]| {
+            for (row, from_row) in from.chunks(other.width as usize).enumerate() {
+                let start = (y as usize + row) * width + x as usize;
+                to[start..start + from_row.len()].copy_from_slice(from_row);
+            }
+        };
+
+        copy(&mut self.pixels, &other.pixels);
+        for ((_, to), (_, from)) in self.aovs.iter_mut().zip(other.aovs.iter()) {
+            copy(to, from);
+        }
+    }
+}
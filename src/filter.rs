@@ -0,0 +1,237 @@
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+
+/// The shape of a reconstruction filter.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    // Mitchell-Netravali, with B = C = 1/3.
+    Mitchell,
+    // A sinc windowed by a sinc that is as wide as the filter.
+    Lanczos,
+}
+
+impl FilterKind {
+    // Radius in pixels used if none is given. A box with radius 0.5 is a plain average of the
+    // samples in each pixel.
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+impl fmt::Display for FilterKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::Mitchell => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for FilterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "box" => Ok(FilterKind::Box),
+            "tent" => Ok(FilterKind::Tent),
+            "gaussian" => Ok(FilterKind::Gaussian),
+            "mitchell" => Ok(FilterKind::Mitchell),
+            "lanczos" => Ok(FilterKind::Lanczos),
+            _ => Err(format!(
+                "unknown filter '{}', expected box, tent, gaussian, mitchell or lanczos",
+                s
+            )),
+        }
+    }
+}
+
+/// Reconstruction filter that decides how much each sample contributes to the pixels around
+/// it. Samples affect all pixels whose centers are closer than `radius` (in pixels) along both
+/// axes.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Filter {
+    /// The largest radius allowed, in pixels. Every tile keeps a margin this wide around it.
+    pub const MAX_RADIUS: f64 = 16.0;
+
+    pub fn new(kind: FilterKind) -> Self {
+        Filter {
+            kind,
+            radius: kind.default_radius(),
+        }
+    }
+
+    /// The weight of a sample at offset (`x`, `y`) from a pixel center. Mitchell and Lanczos
+    /// have negative lobes.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let r = self.radius;
+        let x = x.abs();
+        if x >= r {
+            return 0.0;
+        }
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => r - x,
+            FilterKind::Gaussian => {
+                // Shifted down so that it reaches 0 at the radius, instead of being cut off.
+                let sigma = r / 3.0;
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                gaussian(x) - gaussian(r)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / r),
+            FilterKind::Lanczos => sinc(x) * sinc(x / r),
+        }
+    }
+}
+
+// The Mitchell-Netravali filter for B = C = 1/3, from "Reconstruction Filters in Computer
+// Graphics" (1988). Defined on [0, 2).
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+    let value = if x < 1.0 {
+        (12.0 - 9.0 * B - 6.0 * C) * x.powi(3)
+            + (-18.0 + 12.0 * B + 6.0 * C) * x.powi(2)
+            + (6.0 - 2.0 * B)
+    } else {
+        (-B - 6.0 * C) * x.powi(3)
+            + (6.0 * B + 30.0 * C) * x.powi(2)
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C)
+    };
+    value / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// Pixels whose sum of weights is less than this fraction of the sum of their absolute values
+// are mostly cancelled out by negative lobes, and dividing by it would blow up their color.
+const MIN_RELATIVE_WEIGHT: f64 = 0.1;
+
+/// Weighted sums of samples for a rectangle of pixels, which may reach outside of the image.
+pub(crate) struct SplatBuffer {
+    x: i64,
+    y: i64,
+    width: u32,
+    height: u32,
+    colors: Vec<Vec3>,
+    weights: Vec<f64>,
+    // The same sums with the absolute values of the weights, as a fallback for pixels where the
+    // weights (nearly) cancel out.
+    abs_colors: Vec<Vec3>,
+    abs_weights: Vec<f64>,
+}
+
+impl SplatBuffer {
+    pub fn new(x: i64, y: i64, width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        SplatBuffer {
+            x,
+            y,
+            width,
+            height,
+            colors: vec![Vec3::ZERO; size],
+            weights: vec![0.0; size],
+            abs_colors: vec![Vec3::ZERO; size],
+            abs_weights: vec![0.0; size],
+        }
+    }
+
+    // The index of the pixel at `x`, `y` of the image, if it is inside the buffer.
+    fn index(&self, x: i64, y: i64) -> Option<usize> {
+        let (x, y) = (x - self.x, y - self.y);
+        if x < 0 || y < 0 || x >= i64::from(self.width) || y >= i64::from(self.height) {
+            None
+        } else {
+            Some(y as usize * self.width as usize + x as usize)
+        }
+    }
+
+    /// Adds a sample at position (`x`, `y`) of the image, in pixels from the top left, to all
+    /// pixels in reach of the filter.
+    pub fn splat(&mut self, filter: &Filter, x: f64, y: f64, color: Vec3) {
+        // Pixel centers are at half-integer positions.
+        let (x, y) = (x - 0.5, y - 0.5);
+        let r = filter.radius;
+        for pixel_y in (y - r).ceil() as i64..=(y + r).floor() as i64 {
+            for pixel_x in (x - r).ceil() as i64..=(x + r).floor() as i64 {
+                let weight = filter.evaluate(pixel_x as f64 - x, pixel_y as f64 - y);
+                if weight == 0.0 {
+                    continue;
+                }
+                if let Some(index) = self.index(pixel_x, pixel_y) {
+                    self.colors[index] += weight * color;
+                    self.weights[index] += weight;
+                    self.abs_colors[index] += weight.abs() * color;
+                    self.abs_weights[index] += weight.abs();
+                }
+            }
+        }
+    }
+
+    /// Adds up the samples of `other` with those of this buffer, where they overlap.
+    pub fn merge(&mut self, other: &SplatBuffer) {
+        for y in 0..other.height {
+            for x in 0..other.width {
+                let (image_x, image_y) = (other.x + i64::from(x), other.y + i64::from(y));
+                if let Some(index) = self.index(image_x, image_y) {
+                    let other_index = (y * other.width + x) as usize;
+                    self.colors[index] += other.colors[other_index];
+                    self.weights[index] += other.weights[other_index];
+                    self.abs_colors[index] += other.abs_colors[other_index];
+                    self.abs_weights[index] += other.abs_weights[other_index];
+                }
+            }
+        }
+    }
+
+    /// The filtered color of each pixel, row by row. Pixels that got no weight are black.
+    /// Negative lobes cannot make colors negative, and pixels where they cancel out most of the
+    /// weight fall back to weighting with the absolute value of the filter.
+    pub fn resolve(&self) -> Vec<Vec3> {
+        (0..self.colors.len())
+            .map(|i| {
+                let (weight, abs_weight) = (self.weights[i], self.abs_weights[i]);
+                let color = if weight > 0.0 && weight >= MIN_RELATIVE_WEIGHT * abs_weight {
+                    self.colors[i] / weight
+                } else if abs_weight > 0.0 {
+                    self.abs_colors[i] / abs_weight
+                } else {
+                    Vec3::ZERO
+                };
+                color.map(|c| c.max(0.0))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,304 @@
+use crate::clamp;
+use crate::Vec3;
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+
+/// A floating point RGB image, stored row by row starting at the top.
+pub struct HdrImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vec3>,
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+impl HdrImage {
+    /// Loads a Radiance `.hdr` or a `.pfm` file, depending on the extension of `path`.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        let mut reader = io::BufReader::new(std::fs::File::open(path)?);
+        match extension.as_deref() {
+            Some("hdr") => HdrImage::read_hdr(&mut reader),
+            Some("pfm") => HdrImage::read_pfm(&mut reader),
+            _ => Err(invalid_data(format!(
+                "{}: unsupported image format, expected .hdr or .pfm",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Bilinearly interpolated lookup, with `u` wrapping around horizontally and `v` clamped.
+    /// (0, 0) is the top left corner of the image.
+    pub fn sample(&self, u: f64, v: f64) -> Vec3 {
+        let x = u * self.width as f64 - 0.5;
+        let y = clamp(v * self.height as f64 - 0.5, 0.0, self.height as f64 - 1.0);
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+
+        let column = |x: f64| (x as i64).rem_euclid(self.width as i64) as usize;
+        let (x0, x1) = (column(x0), column(x0 + 1.0));
+        let y0 = y0 as usize;
+        let y1 = (y0 + 1).min(self.height - 1);
+        let pixel = |x: usize, y: usize| self.pixels[y * self.width + x];
+
+        (1.0 - ty) * ((1.0 - tx) * pixel(x0, y0) + tx * pixel(x1, y0))
+            + ty * ((1.0 - tx) * pixel(x0, y1) + tx * pixel(x1, y1))
+    }
+
+    // Radiance RGBE format, see "Real Pixels" in Graphics Gems II and Greg Ward's reference
+    // implementation.
+    fn read_hdr(reader: &mut impl BufRead) -> io::Result<Self> {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if !line.starts_with("#?") {
+            return Err(invalid_data("not a Radiance HDR file"));
+        }
+
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(invalid_data("unexpected end of HDR header"));
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix("FORMAT=") {
+                if format != "32-bit_rle_rgbe" {
+                    return Err(invalid_data(format!("unsupported HDR format {}", format)));
+                }
+            }
+        }
+
+        line.clear();
+        reader.read_line(&mut line)?;
+        let resolution = line.split_whitespace().collect::<Vec<_>>();
+        let (height, width, flip) = match resolution.as_slice() {
+            ["-Y", height, "+X", width] => (height, width, false),
+            ["+Y", height, "+X", width] => (height, width, true),
+            _ => {
+                return Err(invalid_data(format!(
+                    "unsupported HDR orientation '{}'",
+                    line.trim()
+                )))
+            }
+        };
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| invalid_data(format!("invalid HDR resolution '{}'", s)))
+        };
+        let (width, height) = (parse(width)?, parse(height)?);
+        pixel_count(width, height)?;
+
+        // The size of run length encoded data is not known in advance, so the pixels are only
+        // allocated as the rows arrive.
+        let mut pixels = Vec::new();
+        let mut scanline = vec![[0u8; 4]; width];
+        for _ in 0..height {
+            read_hdr_scanline(reader, &mut scanline)?;
+            pixels.extend(scanline.iter().map(|&rgbe| rgbe_to_vec3(rgbe)));
+        }
+
+        if flip {
+            flip_rows(&mut pixels, width);
+        }
+
+        Ok(HdrImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    fn read_pfm(reader: &mut impl BufRead) -> io::Result<Self> {
+        let channels = match read_token(reader)?.as_str() {
+            "PF" => 3,
+            "Pf" => 1,
+            _ => return Err(invalid_data("not a PFM file")),
+        };
+        let parse_size = |s: String| {
+            s.parse::<usize>()
+                .map_err(|_| invalid_data(format!("invalid PFM size '{}'", s)))
+        };
+        let width = parse_size(read_token(reader)?)?;
+        let height = parse_size(read_token(reader)?)?;
+        let scale = read_token(reader)?;
+        let scale = scale
+            .parse::<f32>()
+            .map_err(|_| invalid_data(format!("invalid PFM scale '{}'", scale)))?;
+        let little_endian = scale < 0.0;
+
+        let pixel_count = pixel_count(width, height)?;
+        let size = pixel_count
+            .checked_mul(channels * 4)
+            .ok_or_else(|| invalid_data(format!("PFM size {}x{} is too large", width, height)))?;
+        // Read as far as the file goes instead of allocating what the header claims up front.
+        let mut data = Vec::new();
+        reader.take(size as u64).read_to_end(&mut data)?;
+        if data.len() < size {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!(
+                    "PFM file is too short for its size {}x{}, expected {} bytes of pixels, got {}",
+                    width,
+                    height,
+                    size,
+                    data.len()
+                ),
+            ));
+        }
+        let mut values = data.chunks_exact(4).map(|bytes| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            let value = if little_endian {
+                f32::from_le_bytes(bytes)
+            } else {
+                f32::from_be_bytes(bytes)
+            };
+            f64::from(value)
+        });
+
+        let mut pixels = Vec::with_capacity(pixel_count);
+        for _ in 0..pixel_count {
+            let pixel = if channels == 3 {
+                Vec3(
+                    values.next().unwrap(),
+                    values.next().unwrap(),
+                    values.next().unwrap(),
+                )
+            } else {
+                let value = values.next().unwrap();
+                Vec3(value, value, value)
+            };
+            pixels.push(pixel);
+        }
+
+        // PFM stores the bottom row first.
+        flip_rows(&mut pixels, width);
+
+        Ok(HdrImage {
+            width,
+            height,
+            pixels,
+        })
+    }
+}
+
+// Limits for the size from a file header. 16384x8192 is already about as large as environment
+// maps get.
+const MAX_DIMENSION: usize = 1 << 16;
+const MAX_PIXELS: usize = 1 << 27;
+
+// The number of pixels of an image with the size from a file header, which must not be empty or
+// too large to allocate.
+fn pixel_count(width: usize, height: usize) -> io::Result<usize> {
+    if width == 0 || height == 0 {
+        return Err(invalid_data(format!(
+            "empty image size {}x{}",
+            width, height
+        )));
+    }
+    match width.checked_mul(height) {
+        Some(count) if width <= MAX_DIMENSION && height <= MAX_DIMENSION && count <= MAX_PIXELS => {
+            Ok(count)
+        }
+        _ => Err(invalid_data(format!(
+            "image size {}x{} is too large",
+            width, height
+        ))),
+    }
+}
+
+fn rgbe_to_vec3([r, g, b, e]: [u8; 4]) -> Vec3 {
+    if e == 0 {
+        return Vec3::ZERO;
+    }
+    let f = 2f64.powi(i32::from(e) - (128 + 8));
+    Vec3(f64::from(r) * f, f64::from(g) * f, f64::from(b) * f)
+}
+
+fn read_byte(reader: &mut impl Read) -> io::Result<u8> {
+    let mut byte = [0u8];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn read_hdr_scanline(reader: &mut impl Read, scanline: &mut [[u8; 4]]) -> io::Result<()> {
+    let width = scanline.len();
+    let mut first = [0u8; 4];
+    reader.read_exact(&mut first)?;
+
+    // Scanlines in the "new" run length encoding start with 2, 2 and the width; everything else
+    // is stored flat.
+    let is_rle = (8..0x8000).contains(&width)
+        && first[0] == 2
+        && first[1] == 2
+        && (usize::from(first[2]) << 8 | usize::from(first[3])) == width;
+    if !is_rle {
+        scanline[0] = first;
+        for pixel in scanline[1..].iter_mut() {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(());
+    }
+
+    // Each of the four components is encoded separately.
+    for component in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = read_byte(reader)?;
+            if count > 128 {
+                let count = usize::from(count - 128);
+                if x + count > width {
+                    return Err(invalid_data("HDR run exceeds scanline"));
+                }
+                let value = read_byte(reader)?;
+                for pixel in scanline[x..x + count].iter_mut() {
+                    pixel[component] = value;
+                }
+                x += count;
+            } else {
+                let count = usize::from(count);
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("invalid HDR run length"));
+                }
+                for pixel in scanline[x..x + count].iter_mut() {
+                    pixel[component] = read_byte(reader)?;
+                }
+                x += count;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// Reads a whitespace-delimited token of a PFM header, consuming exactly one whitespace
+// character after it (anything more would already be pixel data).
+fn read_token(reader: &mut impl Read) -> io::Result<String> {
+    let mut token = String::new();
+    loop {
+        let byte = read_byte(reader)?;
+        if byte.is_ascii_whitespace() {
+            if token.is_empty() {
+                continue;
+            }
+            return Ok(token);
+        }
+        token.push(char::from(byte));
+    }
+}
+
+fn flip_rows(pixels: &mut [Vec3], width: usize) {
+    let height = pixels.len() / width;
+    for y in 0..height / 2 {
+        let (top, bottom) = pixels.split_at_mut((height - 1 - y) * width);
+        top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+    }
+}
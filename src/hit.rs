@@ -1,45 +1,154 @@
+use crate::Aabb;
 use crate::Material;
 use crate::Ray;
+use crate::Sampler;
 use crate::Vec3;
+use crate::T_MIN;
 use serde::{Deserialize, Serialize};
+use std::f64::consts::PI;
 use std::ops::Range;
 
 pub struct Hit<'a> {
     pub point: Vec3,
+    // The shading normal, which may be interpolated or perturbed, facing the side the ray came
+    // from. Materials shade with this one.
     pub normal: Vec3,
+    // The actual normal of the surface, on the same side as `normal`.
+    pub geometric_normal: Vec3,
+    // Texture coordinates, and how the point moves along the surface as they change.
+    pub uv: (f64, f64),
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    // Orthonormal to each other and to `normal`. The tangent points towards increasing u, and
+    // the bitangent towards increasing v.
+    pub tangent: Vec3,
+    pub bitangent: Vec3,
+    // Roughly how wide the part of the surface is that the ray stands for (e.g. one pixel), for
+    // filtering textures. 0 if unknown.
+    pub footprint: f64,
     pub t: f64,
     pub front_face: bool,
     pub material: &'a dyn Material,
+    // The (leaf) hittable that was hit, e.g. the whole mesh for triangle meshes.
+    pub object: &'a dyn Hittable,
 }
 
 impl<'a> Hit<'a> {
+    // A hit with zero texture coordinates and an arbitrary tangent frame, see `with_uv` and
+    // `with_shading_normal` for the rest.
     pub fn new(
         point: Vec3,
         outward_normal: Vec3,
         t: f64,
-        ray_direction: Vec3,
+        ray: &Ray,
         material: &'a dyn Material,
+        object: &'a dyn Hittable,
     ) -> Self {
-        let front_face = ray_direction.dot(outward_normal) < 0.0;
+        let front_face = ray.direction.dot(outward_normal) < 0.0;
         let normal = if front_face {
             outward_normal
         } else {
             -outward_normal
         };
 
+        let (dpdu, dpdv) = normal.orthonormal_basis();
         Hit {
             point,
             normal,
+            geometric_normal: normal,
+            uv: (0.0, 0.0),
+            dpdu,
+            dpdv,
+            tangent: dpdu,
+            bitangent: dpdv,
+            footprint: ray.spread * t * ray.direction.mag(),
             t,
             front_face,
             material,
+            object,
         }
     }
+
+    pub fn with_uv(mut self, uv: (f64, f64), dpdu: Vec3, dpdv: Vec3) -> Self {
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self.update_tangents();
+        self
+    }
+
+    // Front faces are still decided by the geometric normal, the shading normal just gets
+    // flipped along with it.
+    pub fn with_shading_normal(mut self, outward_normal: Vec3) -> Self {
+        self.normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+        self.update_tangents();
+        self
+    }
+
+    // Applies the material's bump map, if it has one, to the shading normal. Integrators do this
+    // for the closest hit only, before shading it.
+    pub fn with_bump_map(self) -> Self {
+        let normal = match self.material.bump_map() {
+            Some(bump_map) => bump_map.normal(&self),
+            None => return self,
+        };
+        // Degenerate tangent frames give NaNs, and normals facing away from the surface would
+        // shade it as seen from behind.
+        if !normal.mag_squared().is_finite() || normal.dot(self.geometric_normal) <= 0.0 {
+            return self;
+        }
+        let outward_normal = if self.front_face { normal } else { -normal };
+        self.with_shading_normal(outward_normal)
+    }
+
+    fn update_tangents(&mut self) {
+        let normal = self.normal;
+        let tangent = self.dpdu - normal * normal.dot(self.dpdu);
+        let (tangent, bitangent) = if tangent.mag_squared() > 1e-16 {
+            let tangent = tangent.normalized();
+            (tangent, normal.cross(tangent))
+        } else {
+            // No usable derivative, e.g. at the poles of a sphere.
+            normal.orthonormal_basis()
+        };
+        self.tangent = tangent;
+        self.bitangent = if bitangent.dot(self.dpdv) < 0.0 {
+            -bitangent
+        } else {
+            bitangent
+        };
+    }
 }
 
 #[typetag::serde]
 pub trait Hittable: Sync {
-    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<Hit>;
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<Hit<'_>>;
+
+    // None for hittables that are unbounded.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // Adds all the hittables that show up as `Hit::object`, in a fixed order. Containers recurse
+    // into their children.
+    fn collect_objects<'a>(&'a self, objects: &mut Vec<&'a dyn Hittable>);
+
+    // Adds all hittables with an emissive material to `lights`. Containers recurse into their
+    // children.
+    fn collect_lights<'a>(&'a self, _lights: &mut Vec<&'a dyn Hittable>) {}
+
+    // For lights: the density (over solid angle) with which `random` picks `direction` for a
+    // point at `origin`.
+    fn pdf_value(&self, _origin: Vec3, _direction: Vec3) -> f64 {
+        0.0
+    }
+
+    // For lights: a random direction from `origin` towards a point on the hittable.
+    fn random(&self, _origin: Vec3, _sampler: &mut dyn Sampler) -> Vec3 {
+        Vec3(1.0, 0.0, 0.0)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -51,7 +160,7 @@ pub struct Sphere {
 
 #[typetag::serde]
 impl Hittable for Sphere {
-    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<Hit> {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<Hit<'_>> {
         let oc = ray.origin - self.center;
         let a = ray.direction.mag_squared();
         let half_b = oc.dot(ray.direction);
@@ -63,31 +172,103 @@ impl Hittable for Sphere {
 
             let t = (-half_b - root) / a;
             if t_range.contains(&t) {
-                let point = ray.at(t);
-                return Some(Hit::new(
-                    point,
-                    (point - self.center) / self.radius,
-                    t,
-                    ray.direction,
-                    self.material.as_ref(),
-                ));
+                return Some(self.hit_at(ray, t));
             }
 
             let t = (-half_b + root) / a;
             if t_range.contains(&t) {
-                let point = ray.at(t);
-                return Some(Hit::new(
-                    point,
-                    (point - self.center) / self.radius,
-                    t,
-                    ray.direction,
-                    self.material.as_ref(),
-                ));
+                return Some(self.hit_at(ray, t));
             }
         }
 
         None
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // The radius may be negative to flip the normals (for hollow glass spheres).
+        let radius = self.radius.abs();
+        let extent = Vec3(radius, radius, radius);
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+
+    fn collect_objects<'a>(&'a self, objects: &mut Vec<&'a dyn Hittable>) {
+        objects.push(self);
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<&'a dyn Hittable>) {
+        if self.material.is_emissive() {
+            lights.push(self);
+        }
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        if self
+            .hit(Ray::new(origin, direction), T_MIN..f64::INFINITY)
+            .is_none()
+        {
+            return 0.0;
+        }
+
+        match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => 1.0 / (2.0 * PI * (1.0 - cos_theta_max)),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let to_center = self.center - origin;
+        let cos_theta_max = match self.cos_theta_max(origin) {
+            Some(cos_theta_max) => cos_theta_max,
+            None => return to_center,
+        };
+
+        // Uniformly sample the cone of directions towards the sphere.
+        let (u, v) = sampler.get_2d();
+        let phi = 2.0 * PI * u;
+        let z = 1.0 + v * (cos_theta_max - 1.0);
+        let r = (1.0 - z * z).sqrt();
+
+        let w = to_center.normalized();
+        let (u, v) = w.orthonormal_basis();
+        r * phi.cos() * u + r * phi.sin() * v + z * w
+    }
+}
+
+impl Sphere {
+    fn hit_at(&self, ray: Ray, t: f64) -> Hit<'_> {
+        let point = ray.at(t);
+        let hit = Hit::new(
+            point,
+            (point - self.center) / self.radius,
+            t,
+            &ray,
+            self.material.as_ref(),
+            self,
+        );
+
+        // Spherical coordinates: u goes around the y axis starting at -x, v from the bottom to
+        // the top.
+        let Vec3(x, y, z) = point - self.center;
+        let direction = (point - self.center).normalized();
+        let theta = (-direction.1).acos();
+        let phi = (-direction.2).atan2(direction.0) + PI;
+        let ring_radius = (x * x + z * z).sqrt().max(1e-12);
+        let dpdu = 2.0 * PI * Vec3(z, 0.0, -x);
+        let dpdv = PI * Vec3(-y * x / ring_radius, ring_radius, -y * z / ring_radius);
+        hit.with_uv((phi / (2.0 * PI), theta / PI), dpdu, dpdv)
+    }
+
+    // Cosine of the half-angle of the cone the sphere covers as seen from `origin`, None for
+    // points inside the sphere.
+    fn cos_theta_max(&self, origin: Vec3) -> Option<f64> {
+        let distance_squared = (self.center - origin).mag_squared();
+        let radius_squared = self.radius * self.radius;
+        if distance_squared <= radius_squared {
+            None
+        } else {
+            Some((1.0 - radius_squared / distance_squared).sqrt())
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -97,7 +278,7 @@ pub struct HittableList {
 
 #[typetag::serde]
 impl Hittable for HittableList {
-    fn hit(&self, ray: Ray, mut t_range: Range<f64>) -> Option<Hit> {
+    fn hit(&self, ray: Ray, mut t_range: Range<f64>) -> Option<Hit<'_>> {
         let mut current_hit = None;
 
         for hittable in self.hittables.iter() {
@@ -109,4 +290,24 @@ impl Hittable for HittableList {
 
         current_hit
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut hittables = self.hittables.iter();
+        let first = hittables.next()?.bounding_box()?;
+        hittables.try_fold(first, |bbox, hittable| {
+            Some(bbox.surrounding(hittable.bounding_box()?))
+        })
+    }
+
+    fn collect_objects<'a>(&'a self, objects: &mut Vec<&'a dyn Hittable>) {
+        for hittable in self.hittables.iter() {
+            hittable.collect_objects(objects);
+        }
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<&'a dyn Hittable>) {
+        for hittable in self.hittables.iter() {
+            hittable.collect_lights(lights);
+        }
+    }
 }
@@ -0,0 +1,305 @@
+use crate::Hit;
+use crate::LightList;
+use crate::Ray;
+use crate::Sampler;
+use crate::Scene;
+use crate::Vec3;
+use crate::T_MIN;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A light transport algorithm, computing the color seen along a camera ray.
+pub trait Integrator: Sync {
+    // The radiance arriving at the origin of `ray`, from the direction of `ray`. `hit` is
+    // `scene.hit(ray)`, which the caller already needed for other things (like AOVs).
+    fn radiance(
+        &self,
+        ray: Ray,
+        hit: Option<Hit>,
+        scene: &Scene,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3;
+}
+
+/// Selects the integrator used by `render`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum IntegratorKind {
+    PathTracer,
+    Whitted,
+    // Occlusion within `distance` of the first hit.
+    AmbientOcclusion { distance: f64 },
+}
+
+impl IntegratorKind {
+    pub fn build(self, max_depth: u32) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::PathTracer => Box::new(PathTracer { max_depth }),
+            IntegratorKind::Whitted => Box::new(Whitted { max_depth }),
+            IntegratorKind::AmbientOcclusion { distance } => {
+                Box::new(AmbientOcclusion { distance })
+            }
+        }
+    }
+}
+
+impl fmt::Display for IntegratorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegratorKind::PathTracer => write!(f, "path"),
+            IntegratorKind::Whitted => write!(f, "whitted"),
+            IntegratorKind::AmbientOcclusion { distance } => write!(f, "ao:{}", distance),
+        }
+    }
+}
+
+// Parses "path", "whitted", "ao" or "ao:<distance>", with a positive distance.
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" => Ok(IntegratorKind::PathTracer),
+            "whitted" => Ok(IntegratorKind::Whitted),
+            "ao" => Ok(IntegratorKind::AmbientOcclusion {
+                distance: f64::INFINITY,
+            }),
+            _ => match s.strip_prefix("ao:").map(f64::from_str) {
+                Some(Ok(distance)) if distance > 0.0 => {
+                    Ok(IntegratorKind::AmbientOcclusion { distance })
+                }
+                Some(Ok(distance)) => Err(format!(
+                    "ambient occlusion distance must be positive, got {}",
+                    distance
+                )),
+                _ => Err(format!(
+                    "unknown integrator '{}', expected path, whitted, ao or ao:<distance>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Unidirectional path tracing, with next-event estimation at every surface that supports light
+/// sampling.
+pub struct PathTracer {
+    pub max_depth: u32,
+}
+
+impl Integrator for PathTracer {
+    fn radiance(
+        &self,
+        ray: Ray,
+        hit: Option<Hit>,
+        scene: &Scene,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let mut color = Vec3::ZERO;
+        let mut throughput = Vec3::ONE;
+        let mut ray = ray;
+        // The density with which the material sampled the current ray, or None for camera rays
+        // and rays from materials that cannot be light sampled.
+        let mut scatter_pdf = None;
+        let mut first_hit = Some(hit);
+
+        for _ in 0..self.max_depth {
+            let hit = match first_hit.take().unwrap_or_else(|| scene.hit(ray)) {
+                Some(hit) => hit,
+                None => {
+                    color += throughput * scene.background.color(&ray);
+                    break;
+                }
+            };
+
+            let emitted = hit.material.emitted(&hit);
+            if emitted != Vec3::ZERO {
+                // This light could also have been reached by light sampling at the previous hit,
+                // weigh the two strategies with multiple importance sampling.
+                let weight = scatter_pdf.map_or(1.0, |scatter_pdf| {
+                    power_heuristic(scatter_pdf, lights.pdf_value(ray.origin, ray.direction))
+                });
+                color += throughput * emitted * weight;
+            }
+
+            let (attenuation, scattered) = match hit.material.scatter(&ray, &hit, sampler) {
+                Some(scatter) => scatter,
+                None => break,
+            };
+
+            scatter_pdf = hit.material.pdf(&ray, &hit, scattered.direction);
+            if scatter_pdf.is_some() && !lights.is_empty() {
+                color += throughput * sample_light(&ray, &hit, scene, lights, true, sampler);
+            }
+
+            throughput = throughput * attenuation;
+            ray = scattered;
+        }
+
+        color
+    }
+}
+
+/// Whitted-style ray tracing: direct lighting from the scene's lights at surfaces that support
+/// light sampling, and a single ray from the material's `scatter` everywhere else. That is
+/// perfect reflection and refraction for glass and smooth metals, but rough metals (`fuzz > 0`)
+/// still pick a random direction, so they stay noisy. There is no indirect diffuse lighting, so
+/// e.g. the background only shows up directly or through mirrors.
+pub struct Whitted {
+    pub max_depth: u32,
+}
+
+impl Whitted {
+    // `hit` is `scene.hit(ray)`, see `Integrator::radiance`.
+    fn radiance_with_depth(
+        &self,
+        ray: Ray,
+        hit: Option<Hit>,
+        scene: &Scene,
+        lights: &LightList,
+        depth: u32,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        if depth == 0 {
+            return Vec3::ZERO;
+        }
+
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return scene.background.color(&ray),
+        };
+
+        let emitted = hit.material.emitted(&hit);
+        let (attenuation, scattered) = match hit.material.scatter(&ray, &hit, sampler) {
+            Some(scatter) => scatter,
+            None => return emitted,
+        };
+
+        if hit.material.pdf(&ray, &hit, scattered.direction).is_some() {
+            if lights.is_empty() {
+                emitted
+            } else {
+                emitted + sample_light(&ray, &hit, scene, lights, false, sampler)
+            }
+        } else {
+            let scattered_hit = if depth > 1 {
+                scene.hit(scattered)
+            } else {
+                None
+            };
+            emitted
+                + attenuation
+                    * self.radiance_with_depth(
+                        scattered,
+                        scattered_hit,
+                        scene,
+                        lights,
+                        depth - 1,
+                        sampler,
+                    )
+        }
+    }
+}
+
+impl Integrator for Whitted {
+    fn radiance(
+        &self,
+        ray: Ray,
+        hit: Option<Hit>,
+        scene: &Scene,
+        lights: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        self.radiance_with_depth(ray, hit, scene, lights, self.max_depth, sampler)
+    }
+}
+
+/// Ambient occlusion: the fraction of the (cosine-weighted) hemisphere around the first hit
+/// that is not blocked within `distance`. Rays that hit nothing are white.
+pub struct AmbientOcclusion {
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(
+        &self,
+        _: Ray,
+        hit: Option<Hit>,
+        scene: &Scene,
+        _: &LightList,
+        sampler: &mut dyn Sampler,
+    ) -> Vec3 {
+        let hit = match hit {
+            Some(hit) => hit,
+            None => return Vec3::ONE,
+        };
+
+        let direction = (hit.normal + Vec3::sample_unit_vector(sampler.get_2d())).normalized();
+        let occluded = scene
+            .root
+            .hit(Ray::new(hit.point, direction), T_MIN..self.distance)
+            .is_some();
+        if occluded {
+            Vec3::ZERO
+        } else {
+            Vec3::ONE
+        }
+    }
+}
+
+// Next-event estimation: the light directly arriving at `hit` from a random point on a light.
+// With `use_mis`, it is weighted against the material's own sampling with multiple importance
+// sampling.
+fn sample_light(
+    ray: &Ray,
+    hit: &Hit,
+    scene: &Scene,
+    lights: &LightList,
+    use_mis: bool,
+    sampler: &mut dyn Sampler,
+) -> Vec3 {
+    let direction = lights.random(hit.point, sampler);
+    let light_pdf = lights.pdf_value(hit.point, direction);
+    if light_pdf <= 0.0 {
+        return Vec3::ZERO;
+    }
+
+    let f = hit.material.eval(ray, hit, direction);
+    if f == Vec3::ZERO {
+        return Vec3::ZERO;
+    }
+
+    let light_hit = match scene
+        .root
+        .hit(Ray::new(hit.point, direction), T_MIN..f64::INFINITY)
+    {
+        Some(light_hit) => light_hit,
+        None => return Vec3::ZERO,
+    };
+    let emitted = light_hit.material.emitted(&light_hit);
+    if emitted == Vec3::ZERO {
+        return Vec3::ZERO;
+    }
+
+    let weight = if use_mis {
+        let scatter_pdf = hit.material.pdf(ray, hit, direction).unwrap_or(0.0);
+        power_heuristic(light_pdf, scatter_pdf)
+    } else {
+        1.0
+    };
+    f * emitted * (weight / light_pdf)
+}
+
+// Weight for a sample from a strategy with density `pdf`, combined with another strategy with
+// density `other_pdf`, from Veach's thesis.
+fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let pdf = pdf * pdf;
+    let other_pdf = other_pdf * other_pdf;
+    if pdf + other_pdf == 0.0 {
+        0.0
+    } else {
+        pdf / (pdf + other_pdf)
+    }
+}
@@ -1,131 +1,495 @@
+pub use aabb::Aabb;
+pub use adaptive::AdaptiveSampling;
+use adaptive::SampleStats;
+pub use aov::Aov;
+use aov::{AovPixel, ObjectIds};
+pub use background::{Background, EnvironmentMap};
+pub use bump::BumpMap;
+pub use bvh::Bvh;
 pub use camera::Camera;
-use hit::Hit;
+pub use denoise::{denoise, DENOISE_AOVS};
+pub use exr::{write_exr, ExrLayer, ExrPixelType};
+pub use film::Film;
+use filter::SplatBuffer;
+pub use filter::{Filter, FilterKind};
+pub use hit::Hit;
 pub use hit::{Hittable, HittableList, Sphere};
-pub use material::{Dielectric, Lambertian, Material, Metal};
-use rand::Rng;
+pub use integrator::{AmbientOcclusion, Integrator, IntegratorKind, PathTracer, Whitted};
+pub use light::LightList;
+pub use material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+pub use mipmap::{AddressMode, TextureFilter};
+pub use noise::Perlin;
+pub use obj::{load_obj, ObjError};
+pub use output::{
+    encode_rgb8, write_heatmap, write_image, write_pfm, write_png, ImageFormat, TransferFunction,
+};
 pub use ray::Ray;
+pub use sampler::{Sampler, SamplerKind};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+pub use texture::{
+    CheckerTexture, ConstantTexture, ImageTexture, MarbleTexture, NoiseTexture, Texture,
+    WoodTexture,
+};
+pub use tonemap::{ToneMapOperator, ToneMapping};
+pub use triangle::{Triangle, TriangleMesh};
 pub use vec3::Vec3;
 
+mod aabb;
+mod adaptive;
+mod aov;
+mod background;
+mod bump;
+mod bvh;
 mod camera;
+mod denoise;
+mod exr;
+mod film;
+mod filter;
+mod hdr;
 mod hit;
+mod integrator;
+mod light;
 mod material;
+mod mipmap;
+mod noise;
+mod obj;
+mod output;
 mod ray;
+mod sampler;
+mod texture;
+mod tonemap;
+mod triangle;
 mod vec3;
 
+// Minimum distance along a ray for hits, to avoid self-intersections due to rounding.
+const T_MIN: f64 = 0.000001;
+
 pub struct Scene {
     pub root: Box<dyn Hittable>,
     pub camera: Camera,
+    pub background: Background,
+    // How the scene is meant to be tone mapped, if it cares.
+    pub tone_mapping: Option<ToneMapping>,
+    // How the scene is meant to be rendered, if it cares. `render` does not look at these, they
+    // are only stored with the scene.
+    pub image_settings: Option<ImageSettings>,
+    pub render_settings: Option<RenderSettings>,
 }
 
+impl Scene {
+    /// The closest hit of `ray`, with the bump map of its material applied, ready for shading.
+    pub fn hit(&self, ray: Ray) -> Option<Hit<'_>> {
+        self.root
+            .hit(ray, T_MIN..f64::INFINITY)
+            .map(Hit::with_bump_map)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ImageSettings {
     pub width: u32,
     pub height: u32,
 }
 
+impl Default for ImageSettings {
+    fn default() -> Self {
+        ImageSettings {
+            width: 480,
+            height: 270,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct RenderSettings {
+    // Ignored with adaptive sampling, which has its own minimum and maximum.
     pub samples_per_pixel: u32,
     pub max_depth: u32,
+    // Depends on the machine rather than the scene, and does not change the image, so it is not
+    // stored in scene files.
+    #[serde(skip)]
     pub thread_count: u32,
+    pub integrator: IntegratorKind,
+    pub sampler: SamplerKind,
+    // How the samples are weighted into the pixels around them.
+    pub filter: Filter,
+    // Renders with the same seed (and settings) produce exactly the same image.
+    pub seed: u64,
+    // Which AOVs to produce in addition to the color. All but the variance cost an extra ray
+    // per sample.
+    pub aovs: Vec<Aov>,
+    pub adaptive: Option<AdaptiveSampling>,
 }
 
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            samples_per_pixel: 10,
+            max_depth: 50,
+            thread_count: 1,
+            integrator: IntegratorKind::PathTracer,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::new(FilterKind::Box),
+            seed: 0,
+            aovs: Vec::new(),
+            adaptive: None,
+        }
+    }
+}
+
+// The on-disk format of a scene. Older scene files are just a `(root, camera)` tuple, which
+// `deserialize_scene` still accepts.
+#[derive(Serialize)]
+struct SceneFileRef<'a> {
+    root: &'a dyn Hittable,
+    camera: &'a Camera,
+    background: &'a Background,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tone_mapping: Option<&'a ToneMapping>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image_settings: Option<&'a ImageSettings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    render_settings: Option<&'a RenderSettings>,
+}
+
+#[derive(Deserialize)]
+struct SceneFile {
+    root: Box<dyn Hittable>,
+    camera: Camera,
+    #[serde(default)]
+    background: Background,
+    #[serde(default)]
+    tone_mapping: Option<ToneMapping>,
+    #[serde(default)]
+    image_settings: Option<ImageSettings>,
+    #[serde(default)]
+    render_settings: Option<RenderSettings>,
+}
+
+// Paths in the scene are written relative to the current directory, see `serialize_scene_in`
+// for scenes saved to files.
 pub fn serialize_scene(scene: &Scene) -> ron::Result<String> {
-    ron::ser::to_string_pretty(&(&scene.root, &scene.camera), ron::ser::PrettyConfig::new())
+    serialize_scene_in(scene, Path::new(""))
 }
 
-pub fn deserialize_scene(s: &str) -> ron::Result<Scene> {
-    let (root, camera) = ron::from_str(s)?;
-    Ok(Scene { root, camera })
+/// Like `serialize_scene`, but with paths in the scene written relative to `directory`, which
+/// should be the one the scene file is saved in.
+pub fn serialize_scene_in(scene: &Scene, directory: &Path) -> ron::Result<String> {
+    let file = SceneFileRef {
+        root: scene.root.as_ref(),
+        camera: &scene.camera,
+        background: &scene.background,
+        tone_mapping: scene.tone_mapping.as_ref(),
+        image_settings: scene.image_settings.as_ref(),
+        render_settings: scene.render_settings.as_ref(),
+    };
+    let directory = canonical_path(directory)?;
+    let previous = SCENE_DIRECTORY.with(|d| d.replace(directory));
+    let s = ron::ser::to_string_pretty(&file, ron::ser::PrettyConfig::new());
+    SCENE_DIRECTORY.with(|d| d.replace(previous));
+    s
 }
 
-pub fn render(
-    scene: &Scene,
-    image_settings: &ImageSettings,
-    render_settings: &RenderSettings,
-    show_progress: bool,
-) -> Vec<u8> {
-    (if render_settings.thread_count == 1 {
-        render_thread(scene, image_settings, render_settings, show_progress).into_iter()
+thread_local! {
+    // The absolute directory that relative paths in the scene being (de)serialized are relative
+    // to.
+    static SCENE_DIRECTORY: RefCell<PathBuf> = const { RefCell::new(PathBuf::new()) };
+}
+
+// Where a path from a scene file (e.g. of a texture) points to.
+pub(crate) fn resolve_scene_path(path: &Path) -> PathBuf {
+    SCENE_DIRECTORY.with(|directory| directory.borrow().join(path))
+}
+
+// `path` without symbolic links and `.` or `..` components, or just made absolute if it does not
+// exist. Scenes store paths like this, so that they can be written relative to any directory.
+pub(crate) fn canonical_path(path: &Path) -> io::Result<PathBuf> {
+    // E.g. the parent of a bare file name.
+    let path = if path.as_os_str().is_empty() {
+        Path::new(".")
     } else {
-        crossbeam_utils::thread::scope(|s| {
-            // Start THREAD_COUNT threads, each rendering the scene.
-            let mut thread_results = Vec::with_capacity(render_settings.thread_count as usize);
-            for _ in 0..render_settings.thread_count {
-                thread_results.push(s.spawn(|_| {
-                    render_thread(scene, image_settings, render_settings, show_progress)
-                }));
-            }
+        path
+    };
+    path.canonicalize().or_else(|_| std::path::absolute(path))
+}
 
-            // Accumulate all the results into one buffer.
-            // (We re-use the buffer of one of the threads, just to avoid allocating another one.)
-            let mut result_pixels = thread_results.pop().unwrap().join().unwrap();
-            for thread_pixels in thread_results.into_iter() {
-                let thread_pixels = thread_pixels.join().unwrap();
-                for i in 0..thread_pixels.len() {
-                    result_pixels[i] += thread_pixels[i];
-                }
+// Writes a (canonical) path from the scene relative to the directory of the scene file, as far
+// as they have a common ancestor, so that scenes can be moved along with their files.
+pub(crate) fn serialize_scene_path<S: serde::Serializer>(
+    path: &Path,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let relative = SCENE_DIRECTORY.with(|directory| {
+        let directory = directory.borrow();
+        let mut path_components = path.components().peekable();
+        let mut directory_components = directory.components().peekable();
+        let mut common = 0;
+        while let (Some(a), Some(b)) = (path_components.peek(), directory_components.peek()) {
+            if a != b {
+                break;
             }
-            result_pixels.into_iter()
-        })
-        .unwrap()
+            path_components.next();
+            directory_components.next();
+            common += 1;
+        }
+        // Only the root (or nothing, for other drives) in common.
+        if common <= 1 {
+            return path.to_owned();
+        }
+        directory_components
+            .map(|_| Component::ParentDir)
+            .chain(path_components)
+            .collect::<PathBuf>()
+    });
+    relative.serialize(serializer)
+}
+
+// Relative paths in the scene are relative to the current directory, see
+// `deserialize_scene_in` for scenes loaded from files.
+pub fn deserialize_scene(s: &str) -> ron::Result<Scene> {
+    deserialize_scene_in(s, Path::new(""))
+}
+
+/// Like `deserialize_scene`, but with relative paths in the scene taken to be relative to
+/// `directory`, which should be the one the scene file is in.
+pub fn deserialize_scene_in(s: &str, directory: &Path) -> ron::Result<Scene> {
+    let directory = canonical_path(directory)?;
+    let previous = SCENE_DIRECTORY.with(|d| d.replace(directory));
+    let scene = deserialize_scene_file(s);
+    SCENE_DIRECTORY.with(|d| d.replace(previous));
+    scene
+}
+
+fn deserialize_scene_file(s: &str) -> ron::Result<Scene> {
+    let file = match ron::from_str::<SceneFile>(s) {
+        Ok(file) => file,
+        Err(e) => match ron::from_str(s) {
+            Ok((root, camera)) => SceneFile {
+                root,
+                camera,
+                background: Background::default(),
+                tone_mapping: None,
+                image_settings: None,
+                render_settings: None,
+            },
+            // Report the error for the current format, not the legacy one.
+            Err(_) => return Err(e),
+        },
+    };
+
+    Ok(Scene {
+        root: file.root,
+        camera: file.camera,
+        background: file.background,
+        tone_mapping: file.tone_mapping,
+        image_settings: file.image_settings,
+        render_settings: file.render_settings,
     })
-    // Divide the accumulated colors by the amount of samples, and convert to 0-255 u8 color values.
-    .flat_map(|c| c / (render_settings.samples_per_pixel as f64))
-    .map(|c| (255.0 * (clamp(c, 0.0, 0.999))) as u8)
-    .collect::<Vec<_>>()
 }
 
-fn render_thread(
+// Images are split into square tiles of this size, which the threads take from a shared queue.
+const TILE_SIZE: u32 = 16;
+
+// A rectangle of the image, in pixels starting from the top left.
+#[derive(Copy, Clone)]
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+fn tiles(image_settings: &ImageSettings) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..image_settings.height).step_by(TILE_SIZE as usize) {
+        for x in (0..image_settings.width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(image_settings.width - x),
+                height: TILE_SIZE.min(image_settings.height - y),
+            });
+        }
+    }
+    tiles
+}
+
+pub fn render(
     scene: &Scene,
     image_settings: &ImageSettings,
     render_settings: &RenderSettings,
     show_progress: bool,
-) -> Vec<Vec3> {
-    let mut rng = rand::thread_rng();
-    let mut pixels = Vec::with_capacity((image_settings.width * image_settings.height) as usize);
-
-    for j in (0..image_settings.height).rev() {
-        if show_progress {
-            println!(
-                "Scanline {}/{}",
-                image_settings.height - j,
-                image_settings.height
-            );
-        }
-        for i in 0..image_settings.width {
-            let mut pixel_color = Vec3::ZERO;
-            for _ in 0..render_settings.samples_per_pixel {
-                let u = (f64::from(i) + rng.gen::<f64>()) / f64::from(image_settings.width - 1);
-                let v = (f64::from(j) + rng.gen::<f64>()) / f64::from(image_settings.height - 1);
-
-                let ray = scene.camera.get_ray(u, v);
-                pixel_color += ray_color(ray, scene.root.as_ref(), render_settings.max_depth);
-            }
+) -> Film {
+    let integrator = render_settings.integrator.build(render_settings.max_depth);
+    // The camera always matches the shape of the image, whatever it was set up for.
+    let mut camera = scene.camera.clone();
+    camera.set_aspect_ratio(f64::from(image_settings.width) / f64::from(image_settings.height));
+    let context = RenderContext {
+        scene,
+        camera,
+        image_settings,
+        render_settings,
+        integrator: integrator.as_ref(),
+        lights: LightList::new(scene.root.as_ref()),
+        object_ids: ObjectIds::new(scene.root.as_ref()),
+    };
+
+    let tiles = tiles(image_settings);
+    let next_tile = AtomicUsize::new(0);
+    let finished_tiles = AtomicUsize::new(0);
 
-            pixels.push(pixel_color);
+    // Each worker keeps taking the next tile nobody has started on yet, until there are none
+    // left, so faster threads (or ones with cheaper tiles) simply end up rendering more tiles.
+    let worker = || {
+        let mut results = Vec::new();
+        loop {
+            let index = next_tile.fetch_add(1, Ordering::Relaxed);
+            let tile = match tiles.get(index) {
+                Some(&tile) => tile,
+                None => break,
+            };
+
+            let (tile_film, splats) = render_tile(&context, tile);
+            results.push((index, tile, tile_film, splats));
+
+            // Only report whenever another full percent is done, to not spam the output.
+            let finished = finished_tiles.fetch_add(1, Ordering::Relaxed) + 1;
+            let percent = finished * 100 / tiles.len();
+            if show_progress && percent != (finished - 1) * 100 / tiles.len() {
+                println!("Rendered {}% ({}/{} tiles)", percent, finished, tiles.len());
+            }
         }
+        results
+    };
+
+    let mut tile_results = if render_settings.thread_count == 1 {
+        worker()
+    } else {
+        crossbeam_utils::thread::scope(|s| {
+            let threads = (0..render_settings.thread_count)
+                .map(|_| s.spawn(|_| worker()))
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .flat_map(|thread| thread.join().unwrap())
+                .collect::<Vec<_>>()
+        })
+        .unwrap()
+    };
+
+    // Put the tiles together into one image. Neighbouring tiles add to each other's pixels at
+    // their edges, which has to happen in the same order every time for the result to be exactly
+    // the same.
+    tile_results.sort_by_key(|(index, ..)| *index);
+    let mut film = Film::new(
+        image_settings.width,
+        image_settings.height,
+        &render_settings.aovs,
+    );
+    let mut splats = SplatBuffer::new(0, 0, image_settings.width, image_settings.height);
+    for (_, tile, tile_film, tile_splats) in tile_results.into_iter() {
+        film.copy_from(&tile_film, tile.x, tile.y);
+        splats.merge(&tile_splats);
     }
+    film.pixels = splats.resolve();
 
-    pixels
+    film
 }
 
-fn ray_color(ray: Ray, scene: &dyn Hittable, depth: u32) -> Vec3 {
-    if depth == 0 {
-        return Vec3::ZERO;
-    }
+// Everything the tiles of a render share.
+struct RenderContext<'a> {
+    scene: &'a Scene,
+    camera: Camera,
+    image_settings: &'a ImageSettings,
+    render_settings: &'a RenderSettings,
+    integrator: &'a dyn Integrator,
+    lights: LightList<'a>,
+    object_ids: ObjectIds,
+}
+
+fn render_tile(context: &RenderContext, tile: Tile) -> (Film, SplatBuffer) {
+    let RenderContext {
+        scene,
+        camera,
+        image_settings,
+        render_settings,
+        integrator,
+        lights,
+        object_ids,
+    } = context;
+    // The film only gets the AOVs, the colors are splatted into a buffer that also covers the
+    // pixels around the tile that the filter reaches.
+    let mut film = Film::new(tile.width, tile.height, &render_settings.aovs);
+    let filter = &render_settings.filter;
+    let margin = filter.radius.ceil() as u32;
+    let pixel_spread = camera.pixel_spread(image_settings.height);
+    let mut splats = SplatBuffer::new(
+        i64::from(tile.x) - i64::from(margin),
+        i64::from(tile.y) - i64::from(margin),
+        tile.width + 2 * margin,
+        tile.height + 2 * margin,
+    );
+    let mut material_ids = HashMap::new();
+    let max_samples = match render_settings.adaptive {
+        Some(adaptive) => adaptive.max_samples,
+        None => render_settings.samples_per_pixel,
+    };
+
+    for y in tile.y..tile.y + tile.height {
+        // The camera's v coordinate goes from the bottom to the top of the image.
+        let j = image_settings.height - 1 - y;
+        for i in tile.x..tile.x + tile.width {
+            let seed = pixel_seed(render_settings.seed, image_settings, i, y);
+            let mut sampler = render_settings.sampler.build(seed, max_samples);
+            let mut stats = SampleStats::new();
+            let mut aovs = AovPixel::new(&render_settings.aovs, object_ids, &mut material_ids);
+            while stats.count < max_samples {
+                sampler.start_sample(stats.count);
+                let (du, dv) = sampler.get_2d();
+                let u = (f64::from(i) + du) / f64::from(image_settings.width);
+                let v = (f64::from(j) + dv) / f64::from(image_settings.height);
 
-    match scene.hit(ray, 0.000001..f64::INFINITY) {
-        Some(hit) => hit
-            .material
-            .scatter(&ray, &hit)
-            .map_or(Vec3::ZERO, |(attenuation, scattered)| {
-                attenuation * ray_color(scattered, scene, depth - 1)
-            }),
-        None => {
-            // background
-            let t = 0.5 * (ray.direction.normalized().1 + 1.0);
-            (1.0 - t) * Vec3::ONE + t * Vec3(0.5, 0.7, 1.0)
+                let mut ray = camera.get_ray(u, v, sampler.as_mut());
+                ray.spread = pixel_spread;
+                // The camera ray's hit is traced once, for both the AOVs and the integrator.
+                let hit = scene.hit(ray);
+                if !render_settings.aovs.is_empty() {
+                    aovs.add_sample(ray, hit.as_ref());
+                }
+                let color = integrator.radiance(ray, hit, scene, lights, sampler.as_mut());
+                // Image coordinates go from the top instead.
+                splats.splat(filter, f64::from(i) + du, f64::from(y) + 1.0 - dv, color);
+                stats.add(color);
+
+                if let Some(adaptive) = render_settings.adaptive {
+                    if adaptive.is_converged(&stats) {
+                        break;
+                    }
+                }
+            }
+
+            let index = ((y - tile.y) * tile.width + i - tile.x) as usize;
+            for ((_, pixels), value) in film.aovs.iter_mut().zip(aovs.finish(&stats)) {
+                pixels[index] = value;
+            }
         }
     }
+
+    (film, splats)
+}
+
+// Every pixel gets its own sampler, seeded from the render seed and its position, so that the
+// result does not depend on which thread renders it, or when.
+fn pixel_seed(seed: u64, image_settings: &ImageSettings, x: u32, y: u32) -> u64 {
+    let index = u64::from(y) * u64::from(image_settings.width) + u64::from(x);
+    seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15)
 }
 
 // f64::clamp is... not a thing, and who knows when it will be :(
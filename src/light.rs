@@ -0,0 +1,38 @@
+use crate::Hittable;
+use crate::Sampler;
+use crate::Vec3;
+
+/// All the emissive hittables of a scene, sampled uniformly for next-event estimation.
+pub struct LightList<'a> {
+    lights: Vec<&'a dyn Hittable>,
+}
+
+impl<'a> LightList<'a> {
+    pub fn new(root: &'a dyn Hittable) -> Self {
+        let mut lights = Vec::new();
+        root.collect_lights(&mut lights);
+        LightList { lights }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    pub fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+
+        let sum = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_value(origin, direction))
+            .sum::<f64>();
+        sum / self.lights.len() as f64
+    }
+
+    pub fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let index = (sampler.get_1d() * self.lights.len() as f64) as usize;
+        self.lights[index.min(self.lights.len() - 1)].random(origin, sampler)
+    }
+}
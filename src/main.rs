@@ -1,27 +1,21 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 use raytrace::*;
 
-const ASPECT_RATIO: f64 = 16.0 / 9.0;
-const IMAGE_WIDTH: u32 = 480;
-const IMAGE_HEIGHT: u32 = (IMAGE_WIDTH as f64 / ASPECT_RATIO) as u32;
-const MAX_DEPTH: u32 = 50;
-
-const THREAD_COUNT: u32 = 1;
-const SAMPLES_PER_PIXEL: u32 = 10;
-
 #[allow(dead_code)]
 fn test_scene() -> Scene {
     let ground = Sphere {
         center: Vec3(0.0, -100.5, 1.0),
         radius: 100.0,
         material: Box::new(Lambertian {
-            albedo: Vec3(0.3, 0.8, 0.3),
+            albedo: Vec3(0.3, 0.8, 0.3).into(),
+            bump_map: None,
         }),
     };
 
@@ -29,15 +23,17 @@ fn test_scene() -> Scene {
         center: Vec3(-1.0, 0.0, 1.0),
         radius: 0.5,
         material: Box::new(Lambertian {
-            albedo: Vec3(0.7, 0.1, 0.1),
+            albedo: Vec3(0.7, 0.1, 0.1).into(),
+            bump_map: None,
         }),
     };
     let sphere_middle = Sphere {
         center: Vec3(0.0, 0.0, 2.0),
         radius: 0.5,
         material: Box::new(Metal {
-            albedo: Vec3(0.5, 0.5, 0.5),
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
             fuzz: 0.3,
+            bump_map: None,
         }),
     };
     let sphere_right = Sphere {
@@ -45,6 +41,7 @@ fn test_scene() -> Scene {
         radius: -0.5,
         material: Box::new(Dielectric {
             refraction_index: 1.5,
+            bump_map: None,
         }),
     };
 
@@ -62,17 +59,24 @@ fn test_scene() -> Scene {
         Vec3(0.0, 1.0, 0.3),
         Vec3(0.0, 0.0, 1.0),
         90.0,
-        ASPECT_RATIO,
         0.1,
         5.0,
     );
 
-    Scene { root, camera }
+    Scene {
+        root,
+        camera,
+        background: Background::default(),
+        tone_mapping: None,
+        image_settings: None,
+        render_settings: None,
+    }
 }
 
+// The scene's random layout also comes from `seed`, so that renders of it are reproducible.
 #[allow(dead_code)]
-fn random_scene() -> Scene {
-    let mut rng = rand::thread_rng();
+fn random_scene(seed: u64) -> Scene {
+    let mut rng = Pcg32::seed_from_u64(seed);
 
     let mut objects: Vec<Box<dyn Hittable>> = Vec::new();
 
@@ -81,7 +85,8 @@ fn random_scene() -> Scene {
         center: Vec3(0.0, -1000.0, 0.0),
         radius: 1000.0,
         material: Box::new(Lambertian {
-            albedo: Vec3(0.5, 0.5, 0.5),
+            albedo: Vec3(0.5, 0.5, 0.5).into(),
+            bump_map: None,
         }),
     }));
 
@@ -101,7 +106,8 @@ fn random_scene() -> Scene {
                     center,
                     radius: 0.2,
                     material: Box::new(Lambertian {
-                        albedo: albedo * albedo,
+                        albedo: albedo.into(),
+                        bump_map: None,
                     }),
                 }));
             } else if random_mat < 0.9 {
@@ -115,7 +121,11 @@ fn random_scene() -> Scene {
                 objects.push(Box::new(Sphere {
                     center,
                     radius: 0.2,
-                    material: Box::new(Metal { albedo, fuzz }),
+                    material: Box::new(Metal {
+                        albedo: albedo.into(),
+                        fuzz,
+                        bump_map: None,
+                    }),
                 }));
             } else {
                 // glass
@@ -124,6 +134,7 @@ fn random_scene() -> Scene {
                     radius: 0.2,
                     material: Box::new(Dielectric {
                         refraction_index: 1.5,
+                        bump_map: None,
                     }),
                 }));
             }
@@ -140,66 +151,541 @@ fn random_scene() -> Scene {
         //Vec3(1.0, 0.0, 0.0).cross(up),
         forward,
         30.0,
-        ASPECT_RATIO,
         0.05,
         4.0,
     );
 
-    let root = Box::new(HittableList { hittables: objects });
+    let root = Box::new(Bvh::new(objects));
+
+    Scene {
+        root,
+        camera,
+        background: Background::default(),
+        tone_mapping: None,
+        image_settings: None,
+        render_settings: None,
+    }
+}
+
+// A parallelogram spanned by `u` and `v`, facing in the direction of u x v.
+fn quad(corner: Vec3, u: Vec3, v: Vec3, material: Box<dyn Material>) -> TriangleMesh {
+    TriangleMesh::new(
+        vec![corner, corner + u, corner + u + v, corner + v],
+        vec![],
+        vec![],
+        vec![[0, 1, 2], [0, 2, 3]],
+        material,
+    )
+}
+
+#[allow(dead_code)]
+fn cornell_box() -> Scene {
+    let red = || {
+        Box::new(Lambertian {
+            albedo: Vec3(0.65, 0.05, 0.05).into(),
+            bump_map: None,
+        })
+    };
+    let white = || {
+        Box::new(Lambertian {
+            albedo: Vec3(0.73, 0.73, 0.73).into(),
+            bump_map: None,
+        })
+    };
+    let green = || {
+        Box::new(Lambertian {
+            albedo: Vec3(0.12, 0.45, 0.15).into(),
+            bump_map: None,
+        })
+    };
+
+    let objects: Vec<Box<dyn Hittable>> = vec![
+        Box::new(quad(
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(0.0, 555.0, 0.0),
+            Vec3(0.0, 0.0, 555.0),
+            red(),
+        )),
+        Box::new(quad(
+            Vec3(555.0, 0.0, 0.0),
+            Vec3(0.0, 555.0, 0.0),
+            Vec3(0.0, 0.0, 555.0),
+            green(),
+        )),
+        Box::new(quad(
+            Vec3(0.0, 0.0, 0.0),
+            Vec3(555.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, 555.0),
+            white(),
+        )),
+        Box::new(quad(
+            Vec3(0.0, 555.0, 0.0),
+            Vec3(555.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, 555.0),
+            white(),
+        )),
+        Box::new(quad(
+            Vec3(0.0, 0.0, 555.0),
+            Vec3(555.0, 0.0, 0.0),
+            Vec3(0.0, 555.0, 0.0),
+            white(),
+        )),
+        // Faces down, into the box.
+        Box::new(quad(
+            Vec3(213.0, 554.0, 227.0),
+            Vec3(130.0, 0.0, 0.0),
+            Vec3(0.0, 0.0, 105.0),
+            Box::new(DiffuseLight {
+                emit: Vec3(15.0, 15.0, 15.0).into(),
+            }),
+        )),
+        Box::new(Sphere {
+            center: Vec3(190.0, 90.0, 190.0),
+            radius: 90.0,
+            material: Box::new(Dielectric {
+                refraction_index: 1.5,
+                bump_map: None,
+            }),
+        }),
+        Box::new(Sphere {
+            center: Vec3(370.0, 120.0, 370.0),
+            radius: 120.0,
+            material: Box::new(Metal {
+                albedo: Vec3(0.8, 0.85, 0.88).into(),
+                fuzz: 0.05,
+                bump_map: None,
+            }),
+        }),
+    ];
+
+    let camera = Camera::new(
+        Vec3(278.0, 278.0, -800.0),
+        Vec3(0.0, 1.0, 0.0),
+        Vec3(0.0, 0.0, 1.0),
+        40.0,
+        0.0,
+        800.0,
+    );
+
+    Scene {
+        root: Box::new(Bvh::new(objects)),
+        camera,
+        background: Background::Solid(Vec3::ZERO),
+        // The light itself is far brighter than white.
+        tone_mapping: Some(ToneMapping {
+            operator: ToneMapOperator::Aces,
+            ..ToneMapping::default()
+        }),
+        image_settings: None,
+        render_settings: None,
+    }
+}
+
+// Places the model from an OBJ file in front of a camera looking at it along +z.
+fn obj_scene(path: &Path) -> Result<Scene, Box<dyn Error>> {
+    let root = Bvh::new(load_obj(path)?);
+    let bbox = root
+        .bounding_box()
+        .ok_or_else(|| format!("{} contains no faces", path.display()))?;
+
+    let vfov: f64 = 40.0;
+    let center = bbox.centroid();
+    let radius = 0.5 * bbox.extent().mag();
+    let distance = radius / (vfov.to_radians() / 2.0).sin();
+
+    let camera = Camera::new(
+        center - Vec3(0.0, 0.0, distance),
+        Vec3(0.0, 1.0, 0.0),
+        Vec3(0.0, 0.0, 1.0),
+        vfov,
+        0.0,
+        distance,
+    );
+
+    Ok(Scene {
+        root: Box::new(root),
+        camera,
+        background: Background::default(),
+        tone_mapping: None,
+        image_settings: None,
+        render_settings: None,
+    })
+}
 
-    Scene { root, camera }
+// Parses an aspect ratio given as a number, or as <width>:<height>.
+fn parse_aspect_ratio(s: &str) -> Result<f64, String> {
+    let ratio = match s.find(':') {
+        Some(colon) => {
+            let width = s[..colon].parse::<f64>().map_err(|e| e.to_string())?;
+            let height = s[colon + 1..].parse::<f64>().map_err(|e| e.to_string())?;
+            width / height
+        }
+        None => s.parse::<f64>().map_err(|e| e.to_string())?,
+    };
+    if ratio.is_finite() && ratio > 0.0 {
+        Ok(ratio)
+    } else {
+        Err(format!("invalid aspect ratio '{}'", s))
+    }
 }
 
+/// Image and render options that are not given use the settings stored in the scene file, if
+/// it has them, and the defaults mentioned below otherwise.
 #[derive(Debug, StructOpt)]
 #[structopt(name = "raytrace")]
 struct Opt {
+    /// Width of the image, in pixels. Defaults to 480.
+    #[structopt(long = "width")]
+    width: Option<u32>,
+
+    /// Height of the image, in pixels. Defaults to the width divided by the aspect ratio.
+    #[structopt(long = "height", conflicts_with = "aspect-ratio")]
+    height: Option<u32>,
+
+    /// Aspect ratio of the image, as a number or <width>:<height>, if no height is given.
+    /// Defaults to 16:9.
+    #[structopt(long = "aspect-ratio", parse(try_from_str = parse_aspect_ratio))]
+    aspect_ratio: Option<f64>,
+
+    /// Samples per pixel, without --adaptive. Defaults to 10.
+    #[structopt(long = "samples")]
+    samples: Option<u32>,
+
+    /// Maximum number of bounces of a path. Defaults to 50.
+    #[structopt(long = "max-depth")]
+    max_depth: Option<u32>,
+
+    /// Number of rendering threads. Defaults to the number of available cores.
+    #[structopt(long = "threads")]
+    threads: Option<u32>,
+
+    /// Seed for the random numbers, including the layout of the default scene. Renders with the
+    /// same seed and options are identical. Defaults to 0.
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+
+    /// Save the scene, together with the image and render settings used for it. Paths of
+    /// textures and environment maps in it are written relative to the scene file.
     #[structopt(long = "save-scene", parse(from_os_str))]
     save_scene: Option<PathBuf>,
 
+    /// Load a scene file. Paths of textures and environment maps in it are relative to the
+    /// scene file.
     #[structopt(long = "load-scene", parse(from_os_str))]
     load_scene: Option<PathBuf>,
 
+    /// Load a Wavefront OBJ model. Together with --load-scene, the model is added to that scene.
+    #[structopt(long = "load-obj", parse(from_os_str))]
+    load_obj: Option<PathBuf>,
+
+    /// Light the scene with an equirectangular .hdr or .pfm environment map.
+    #[structopt(long = "environment", parse(from_os_str))]
+    environment: Option<PathBuf>,
+
+    /// Rotation of the environment map around the vertical axis, in degrees.
+    #[structopt(long = "environment-rotation", default_value = "0")]
+    environment_rotation: f64,
+
+    /// Light transport algorithm: path, whitted, ao or ao:<distance>. Defaults to path.
+    #[structopt(long = "integrator")]
+    integrator: Option<IntegratorKind>,
+
+    /// Tone mapping operator: clamp, reinhard, reinhard-extended, aces or hable. Overrides the
+    /// one set in the scene, if any.
+    #[structopt(long = "tone-map")]
+    tone_map: Option<ToneMapOperator>,
+
+    /// Exposure adjustment before tone mapping, in stops.
+    #[structopt(long = "exposure", allow_hyphen_values = true)]
+    exposure: Option<f64>,
+
+    /// Linear value that is mapped to white by the clamp, reinhard-extended and hable operators.
+    #[structopt(long = "white-point")]
+    white_point: Option<f64>,
+
+    /// Sampler for the pixel, lens and light transport dimensions: independent, stratified,
+    /// halton or sobol. Defaults to sobol.
+    #[structopt(long = "sampler")]
+    sampler: Option<SamplerKind>,
+
+    /// Reconstruction filter that weights samples into the pixels around them: box, tent,
+    /// gaussian, mitchell or lanczos. Defaults to box.
+    #[structopt(long = "filter")]
+    filter: Option<FilterKind>,
+
+    /// Radius of the reconstruction filter, in pixels. Defaults to 0.5 for box, 1 for tent, 1.5
+    /// for gaussian, 2 for mitchell and 3 for lanczos. At most 16.
+    #[structopt(long = "filter-radius")]
+    filter_radius: Option<f64>,
+
+    /// Curve used to encode the linear image into the PNG: srgb, linear or gamma:<gamma>.
+    #[structopt(long = "transfer", default_value = "srgb")]
+    transfer: TransferFunction,
+
+    /// Additional outputs, comma separated: depth, normal, albedo, object_id, material_id,
+    /// variance and samples (the number of samples taken per pixel). They are written as layers of
+    /// EXR output, and as <name>.<aov>.pfm otherwise. Defaults to none.
+    #[structopt(long = "aovs", use_delimiter = true, number_of_values = 1)]
+    aovs: Vec<Aov>,
+
+    /// Sample adaptively, until the relative error of each pixel is below this threshold (e.g.
+    /// 0.02), or it reached --max-samples.
+    #[structopt(long = "adaptive")]
+    adaptive: Option<f64>,
+
+    /// Samples every pixel gets with --adaptive, no matter how noisy it is. Defaults to 8.
+    #[structopt(long = "min-samples")]
+    min_samples: Option<u32>,
+
+    /// Maximum samples per pixel with --adaptive. Defaults to 64.
+    #[structopt(long = "max-samples")]
+    max_samples: Option<u32>,
+
+    /// Write a PNG that shows how many samples each pixel got, from blue (none) to red (the
+    /// maximum).
+    #[structopt(long = "sample-heatmap", parse(from_os_str))]
+    sample_heatmap: Option<PathBuf>,
+
+    /// Denoise the image, guided by the normal, albedo and depth AOVs (which are rendered
+    /// for it, but only written if requested with --aovs).
+    #[structopt(long = "denoise")]
+    denoise: bool,
+
+    /// Store the channels of EXR output as half instead of full (32-bit) floats.
+    #[structopt(long = "exr-half")]
+    exr_half: bool,
+
+    /// Format of the output image: png, pfm or exr. Defaults to the extension of FILE.
+    #[structopt(long = "format")]
+    format: Option<ImageFormat>,
+
+    /// Output image, as .png, or linear (not tone mapped) .pfm or .exr.
     #[structopt(name = "FILE", parse(from_os_str))]
     output_file: PathBuf,
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
-    // Output file
     let opt = Opt::from_args();
+    // Fail early, not only after rendering.
+    let format = match opt.format {
+        Some(format) => format,
+        None => ImageFormat::from_path(&opt.output_file)?,
+    };
+    let thread_count = match opt.threads {
+        Some(0) => return Err("thread count must be at least 1".into()),
+        Some(threads) => threads,
+        None => std::thread::available_parallelism().map_or(1, |n| n.get() as u32),
+    };
 
-    let file = BufWriter::new(File::create(&opt.output_file).unwrap());
-    let mut encoder = png::Encoder::new(file, IMAGE_WIDTH, IMAGE_HEIGHT);
-    encoder.set_color(png::ColorType::RGB);
-    encoder.set_depth(png::BitDepth::Eight);
-    let mut writer = encoder.write_header()?;
+    let mut scene = match (opt.load_scene, opt.load_obj) {
+        (Some(path), obj_path) => {
+            let file_content = std::fs::read_to_string(&path)?;
+            let directory = path.parent().unwrap_or_else(|| Path::new(""));
+            let mut scene = deserialize_scene_in(&file_content, directory)?;
+            if let Some(obj_path) = obj_path {
+                let root = scene.root;
+                scene.root = Box::new(HittableList {
+                    hittables: vec![root, Box::new(Bvh::new(load_obj(&obj_path)?))],
+                });
+            }
+            scene
+        }
+        (None, Some(obj_path)) => obj_scene(&obj_path)?,
+        (None, None) => random_scene(opt.seed.unwrap_or(RenderSettings::default().seed)),
+    };
 
-    let scene = match opt.load_scene {
-        Some(path) => {
-            let file_content = std::fs::read_to_string(path)?;
-            deserialize_scene(&file_content)?
+    if let Some(path) = opt.environment {
+        scene.background =
+            Background::Environment(EnvironmentMap::new(path, opt.environment_rotation)?);
+    }
+
+    // Command line options take precedence over the scene's settings.
+    let mut image_settings = scene.image_settings.unwrap_or_default();
+    let scene_aspect_ratio = f64::from(image_settings.width) / f64::from(image_settings.height);
+    if let Some(width) = opt.width {
+        image_settings.width = width;
+    }
+    image_settings.height = match (opt.height, opt.aspect_ratio) {
+        (Some(height), _) => height,
+        (None, Some(aspect_ratio)) => {
+            (f64::from(image_settings.width) / aspect_ratio).round() as u32
         }
-        None => random_scene(),
+        // Keep the scene's shape if only the width changes.
+        (None, None) => (f64::from(image_settings.width) / scene_aspect_ratio).round() as u32,
     };
+    if image_settings.width == 0 || image_settings.height == 0 {
+        return Err(format!(
+            "image size must not be zero, got {}x{}",
+            image_settings.width, image_settings.height
+        )
+        .into());
+    }
 
+    let mut render_settings = scene.render_settings.clone().unwrap_or_default();
+    render_settings.thread_count = thread_count;
+    if let Some(samples) = opt.samples {
+        render_settings.samples_per_pixel = samples;
+    }
+    if let Some(max_depth) = opt.max_depth {
+        render_settings.max_depth = max_depth;
+    }
+    if let Some(seed) = opt.seed {
+        render_settings.seed = seed;
+    }
+    if let Some(integrator) = opt.integrator {
+        render_settings.integrator = integrator;
+    }
+    if let Some(sampler) = opt.sampler {
+        render_settings.sampler = sampler;
+    }
+    if let Some(filter) = opt.filter {
+        render_settings.filter = Filter::new(filter);
+    }
+    if let Some(radius) = opt.filter_radius {
+        render_settings.filter.radius = radius;
+    }
+    if !opt.aovs.is_empty() {
+        render_settings.aovs = opt.aovs.clone();
+    }
+    if let Some(threshold) = opt.adaptive {
+        render_settings.adaptive = Some(match render_settings.adaptive {
+            Some(adaptive) => AdaptiveSampling {
+                threshold,
+                ..adaptive
+            },
+            None => AdaptiveSampling {
+                min_samples: 8,
+                max_samples: 64,
+                threshold,
+            },
+        });
+    }
+    if let Some(adaptive) = &mut render_settings.adaptive {
+        if let Some(min_samples) = opt.min_samples {
+            adaptive.min_samples = min_samples;
+        }
+        if let Some(max_samples) = opt.max_samples {
+            adaptive.max_samples = max_samples;
+        }
+    }
+    // With adaptive sampling, the minimum and maximum take the place of the sample count.
+    if let Some(adaptive) = &render_settings.adaptive {
+        if adaptive.max_samples == 0 {
+            return Err("maximum samples per pixel must be at least 1".into());
+        }
+        if adaptive.min_samples > adaptive.max_samples {
+            return Err(format!(
+                "minimum samples per pixel ({}) must not be more than the maximum ({})",
+                adaptive.min_samples, adaptive.max_samples
+            )
+            .into());
+        }
+        if !adaptive.threshold.is_finite() || adaptive.threshold <= 0.0 {
+            return Err(format!(
+                "adaptive sampling threshold must be a positive number, got {}",
+                adaptive.threshold
+            )
+            .into());
+        }
+    } else if render_settings.samples_per_pixel == 0 {
+        return Err("samples per pixel must be at least 1".into());
+    }
+    let radius = render_settings.filter.radius;
+    if radius.is_nan() || radius <= 0.0 || radius > Filter::MAX_RADIUS {
+        return Err(format!(
+            "filter radius must be positive and at most {}, got {}",
+            Filter::MAX_RADIUS,
+            radius
+        )
+        .into());
+    }
+    if let IntegratorKind::AmbientOcclusion { distance } = render_settings.integrator {
+        if distance.is_nan() || distance <= 0.0 {
+            return Err(format!(
+                "ambient occlusion distance must be positive, got {}",
+                distance
+            )
+            .into());
+        }
+    }
+
+    let mut tone_mapping = scene.tone_mapping.unwrap_or_default();
+    if let Some(operator) = opt.tone_map {
+        tone_mapping.operator = operator;
+    }
+    if let Some(exposure) = opt.exposure {
+        tone_mapping.exposure = exposure;
+    }
+    if let Some(white_point) = opt.white_point {
+        tone_mapping.white_point = Some(white_point);
+    }
+    if let Some(white_point) = tone_mapping.white_point {
+        if !white_point.is_finite() || white_point <= 0.0 {
+            return Err(
+                format!("white point must be a positive number, got {}", white_point).into(),
+            );
+        }
+    }
+
+    // Saved scenes reproduce this render.
     if let Some(path) = opt.save_scene {
+        scene.image_settings = Some(image_settings);
+        scene.render_settings = Some(render_settings.clone());
+        scene.tone_mapping = Some(tone_mapping);
         let mut scene_file = BufWriter::new(File::create(&path).unwrap());
-        scene_file.write_all(&serialize_scene(&scene).unwrap().into_bytes())?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+        scene_file.write_all(&serialize_scene_in(&scene, directory)?.into_bytes())?;
         scene_file.flush()?;
     }
 
-    let image_settings = ImageSettings {
-        width: IMAGE_WIDTH,
-        height: IMAGE_HEIGHT,
-    };
-    let render_settings = RenderSettings {
-        samples_per_pixel: SAMPLES_PER_PIXEL,
-        max_depth: MAX_DEPTH,
-        thread_count: THREAD_COUNT,
-    };
+    // The denoiser and the heatmap need some AOVs, which are only written out if they were
+    // requested.
+    let requested_aovs = render_settings.aovs.clone();
+    let mut needed_aovs = Vec::new();
+    if opt.denoise {
+        needed_aovs.extend_from_slice(&DENOISE_AOVS);
+    }
+    if opt.sample_heatmap.is_some() {
+        needed_aovs.push(Aov::SampleCount);
+    }
+    for aov in needed_aovs {
+        if !render_settings.aovs.contains(&aov) {
+            render_settings.aovs.push(aov);
+        }
+    }
 
-    let png_pixels = render(&scene, &image_settings, &render_settings, true);
+    let mut film = render(&scene, &image_settings, &render_settings, true);
+    if opt.denoise {
+        denoise(&mut film);
+    }
+    if let Some(path) = &opt.sample_heatmap {
+        let counts = film.aov(Aov::SampleCount).unwrap();
+        let max_samples = render_settings
+            .adaptive
+            .map_or(render_settings.samples_per_pixel, |a| a.max_samples);
+        write_heatmap(
+            path,
+            film.width,
+            film.height,
+            counts,
+            f64::from(max_samples),
+        )?;
+    }
+    film.aovs.retain(|(aov, _)| requested_aovs.contains(aov));
 
-    writer.write_image_data(&png_pixels)?;
+    let exr_pixel_type = if opt.exr_half {
+        ExrPixelType::Half
+    } else {
+        ExrPixelType::Float
+    };
+    write_image(
+        &opt.output_file,
+        &film,
+        format,
+        &tone_mapping,
+        opt.transfer,
+        exr_pixel_type,
+    )?;
 
     println!("Done.");
     Ok(())
@@ -1,55 +1,130 @@
+use crate::texture;
+use crate::BumpMap;
 use crate::Hit;
 use crate::Ray;
+use crate::Sampler;
+use crate::Texture;
 use crate::Vec3;
-use rand::Rng;
 use serde::{Deserialize, Serialize};
 
 #[typetag::serde]
 pub trait Material: Sync {
-    fn scatter(&self, ray_in: &Ray, hit: &Hit) -> Option<(Vec3, Ray)>;
+    fn scatter(&self, ray_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Option<(Vec3, Ray)>;
+
+    fn emitted(&self, _hit: &Hit) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    // Emissive materials make the hittables using them be sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    // The BSDF times the cosine term, for light arriving from `direction` and leaving towards
+    // the origin of `ray_in`.
+    fn eval(&self, _ray_in: &Ray, _hit: &Hit, _direction: Vec3) -> Vec3 {
+        Vec3::ZERO
+    }
+
+    // The density (over solid angle) with which `scatter` picks `direction`. None for materials
+    // that scatter into a single (or otherwise unknown) distribution of directions, which then
+    // do not use light sampling.
+    fn pdf(&self, _ray_in: &Ray, _hit: &Hit, _direction: Vec3) -> Option<f64> {
+        None
+    }
+
+    // The overall surface color, for the albedo AOV (and denoising).
+    fn albedo(&self, _hit: &Hit) -> Vec3 {
+        Vec3::ONE
+    }
+
+    // Perturbs the shading normal of hits before the material sees them, see
+    // `Hit::with_bump_map`.
+    fn bump_map(&self) -> Option<&BumpMap> {
+        None
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Lambertian {
-    pub albedo: Vec3,
+    #[serde(deserialize_with = "texture::deserialize")]
+    pub albedo: Box<dyn Texture>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<BumpMap>,
 }
 
 #[typetag::serde]
 impl Material for Lambertian {
-    fn scatter(&self, _: &Ray, hit: &Hit) -> Option<(Vec3, Ray)> {
-        let scatter_direction = hit.normal + Vec3::random_unit_vector();
+    fn scatter(&self, _: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Option<(Vec3, Ray)> {
+        let scatter_direction = hit.normal + Vec3::sample_unit_vector(sampler.get_2d());
         let scattered = Ray::new(hit.point, scatter_direction);
-        Some((self.albedo, scattered))
+        Some((self.albedo.value(hit), scattered))
+    }
+
+    fn eval(&self, _: &Ray, hit: &Hit, direction: Vec3) -> Vec3 {
+        let cosine = hit.normal.dot(direction.normalized());
+        if cosine > 0.0 {
+            self.albedo.value(hit) * (cosine / std::f64::consts::PI)
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    fn pdf(&self, _: &Ray, hit: &Hit, direction: Vec3) -> Option<f64> {
+        // `scatter` picks a cosine-weighted direction.
+        let cosine = hit.normal.dot(direction.normalized());
+        Some(cosine.max(0.0) / std::f64::consts::PI)
+    }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.albedo.value(hit)
+    }
+
+    fn bump_map(&self) -> Option<&BumpMap> {
+        self.bump_map.as_ref()
     }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Metal {
-    pub albedo: Vec3,
+    #[serde(deserialize_with = "texture::deserialize")]
+    pub albedo: Box<dyn Texture>,
     pub fuzz: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<BumpMap>,
 }
 
 #[typetag::serde]
 impl Material for Metal {
-    fn scatter(&self, ray_in: &Ray, hit: &Hit) -> Option<(Vec3, Ray)> {
+    fn scatter(&self, ray_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Option<(Vec3, Ray)> {
         let reflected = ray_in.direction.normalized().reflect(hit.normal)
-            + self.fuzz * Vec3::random_in_unit_sphere();
+            + self.fuzz * Vec3::sample_in_unit_sphere(sampler.get_2d(), sampler.get_1d());
         if reflected.dot(hit.normal) > 0.0 {
-            Some((self.albedo, Ray::new(hit.point, reflected)))
+            Some((self.albedo.value(hit), Ray::new(hit.point, reflected)))
         } else {
             None
         }
     }
+
+    fn albedo(&self, hit: &Hit) -> Vec3 {
+        self.albedo.value(hit)
+    }
+
+    fn bump_map(&self) -> Option<&BumpMap> {
+        self.bump_map.as_ref()
+    }
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Dielectric {
     pub refraction_index: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bump_map: Option<BumpMap>,
 }
 
 #[typetag::serde]
 impl Material for Dielectric {
-    fn scatter(&self, ray_in: &Ray, hit: &Hit) -> Option<(Vec3, Ray)> {
+    fn scatter(&self, ray_in: &Ray, hit: &Hit, sampler: &mut dyn Sampler) -> Option<(Vec3, Ray)> {
         let etai_over_etat = if hit.front_face {
             1.0 / self.refraction_index
         } else {
@@ -67,9 +142,8 @@ impl Material for Dielectric {
             ));
         }
 
-        let mut rng = rand::thread_rng();
         let reflect_prob = self.schlick(cos_theta);
-        if rng.gen::<f64>() < reflect_prob {
+        if sampler.get_1d() < reflect_prob {
             return Some((
                 Vec3::ONE,
                 Ray::new(hit.point, normalized_direction.reflect(hit.normal)),
@@ -79,6 +153,10 @@ impl Material for Dielectric {
         let refracted = normalized_direction.refract(hit.normal, etai_over_etat);
         Some((Vec3::ONE, Ray::new(hit.point, refracted)))
     }
+
+    fn bump_map(&self) -> Option<&BumpMap> {
+        self.bump_map.as_ref()
+    }
 }
 
 impl Dielectric {
@@ -88,3 +166,29 @@ impl Dielectric {
         r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
     }
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct DiffuseLight {
+    #[serde(deserialize_with = "texture::deserialize")]
+    pub emit: Box<dyn Texture>,
+}
+
+#[typetag::serde]
+impl Material for DiffuseLight {
+    fn scatter(&self, _: &Ray, _: &Hit, _: &mut dyn Sampler) -> Option<(Vec3, Ray)> {
+        None
+    }
+
+    fn emitted(&self, hit: &Hit) -> Vec3 {
+        // Only emit on the front side, so e.g. a ceiling light does not light up the ceiling.
+        if hit.front_face {
+            self.emit.value(hit)
+        } else {
+            Vec3::ZERO
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,223 @@
+use crate::clamp;
+use crate::hdr::HdrImage;
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock, Weak};
+
+/// What texture lookups outside of [0, 1] do.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AddressMode {
+    // Repeat the image.
+    Wrap,
+    // Repeat the edge pixels.
+    Clamp,
+    // Repeat the image, flipped every other time.
+    Mirror,
+}
+
+/// How texture lookups combine the pixels around them.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TextureFilter {
+    // The closest pixel.
+    Nearest,
+    // The four closest pixels.
+    Bilinear,
+    // Bilinear lookups in the two mip levels whose pixel size is closest to the lookup's
+    // footprint, so that far away or small textures do not alias.
+    Trilinear,
+}
+
+/// An image together with successively halved versions of it, down to a single pixel.
+pub(crate) struct MipMap {
+    // Level 0 is the full image.
+    levels: Vec<HdrImage>,
+}
+
+// Loaded images, by path and whether they are sRGB encoded. Only weak references are kept, so
+// images go away with the last texture using them.
+type Cache = Mutex<HashMap<(PathBuf, bool), Weak<MipMap>>>;
+static CACHE: OnceLock<Cache> = OnceLock::new();
+
+impl MipMap {
+    /// Loads a PNG file, or reuses it if it is loaded already. With `srgb`, the pixel values are
+    /// decoded from sRGB into linear values, otherwise they are used as they are.
+    pub fn load(path: &Path, srgb: bool) -> io::Result<Arc<Self>> {
+        let key = (path.to_path_buf(), srgb);
+        let cache = CACHE.get_or_init(Default::default);
+        if let Some(mipmap) = cache.lock().unwrap().get(&key).and_then(Weak::upgrade) {
+            return Ok(mipmap);
+        }
+
+        // Loading happens without the lock held, so in the worst case an image gets loaded
+        // twice at the same time.
+        let mipmap = Arc::new(MipMap::new(read_png(path, srgb)?));
+        cache.lock().unwrap().insert(key, Arc::downgrade(&mipmap));
+        Ok(mipmap)
+    }
+
+    pub fn new(image: HdrImage) -> Self {
+        let mut levels = vec![image];
+        loop {
+            let last = levels.last().unwrap();
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            levels.push(downsample(last));
+        }
+        MipMap { levels }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    /// The filtered color at `u`, `v`, where (0, 0) is the top left and (1, 1) the bottom right
+    /// corner of the image. `width` is the size of the area to filter over, in pixels of the
+    /// full image, which only trilinear filtering takes into account.
+    pub fn lookup(
+        &self,
+        u: f64,
+        v: f64,
+        width: f64,
+        filter: TextureFilter,
+        address: AddressMode,
+    ) -> Vec3 {
+        match filter {
+            TextureFilter::Nearest => {
+                let image = &self.levels[0];
+                let x = (u * image.width as f64).floor() as i64;
+                let y = (v * image.height as f64).floor() as i64;
+                texel(image, x, y, address)
+            }
+            TextureFilter::Bilinear => bilinear(&self.levels[0], u, v, address),
+            TextureFilter::Trilinear => {
+                let last = (self.levels.len() - 1) as f64;
+                let level = clamp(width.max(1e-12).log2(), 0.0, last);
+                let below = level.floor();
+                let t = level - below;
+                let below = below as usize;
+                let color = bilinear(&self.levels[below], u, v, address);
+                if t == 0.0 {
+                    color
+                } else {
+                    (1.0 - t) * color + t * bilinear(&self.levels[below + 1], u, v, address)
+                }
+            }
+        }
+    }
+}
+
+fn bilinear(image: &HdrImage, u: f64, v: f64, address: AddressMode) -> Vec3 {
+    let x = u * image.width as f64 - 0.5;
+    let y = v * image.height as f64 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (x - x0, y - y0);
+    let (x0, y0) = (x0 as i64, y0 as i64);
+
+    let texel = |x, y| texel(image, x, y, address);
+    (1.0 - ty) * ((1.0 - tx) * texel(x0, y0) + tx * texel(x0 + 1, y0))
+        + ty * ((1.0 - tx) * texel(x0, y0 + 1) + tx * texel(x0 + 1, y0 + 1))
+}
+
+fn texel(image: &HdrImage, x: i64, y: i64, address: AddressMode) -> Vec3 {
+    let x = apply_address_mode(x, image.width, address);
+    let y = apply_address_mode(y, image.height, address);
+    image.pixels[y * image.width + x]
+}
+
+fn apply_address_mode(i: i64, size: usize, address: AddressMode) -> usize {
+    let size = size as i64;
+    let i = match address {
+        AddressMode::Wrap => i.rem_euclid(size),
+        AddressMode::Clamp => i.max(0).min(size - 1),
+        AddressMode::Mirror => {
+            let i = i.rem_euclid(2 * size);
+            if i < size {
+                i
+            } else {
+                2 * size - 1 - i
+            }
+        }
+    };
+    i as usize
+}
+
+// Halves the size of the image (rounding up), averaging blocks of 2x2 pixels. For odd sizes,
+// the last row or column is averaged with itself.
+fn downsample(image: &HdrImage) -> HdrImage {
+    let width = image.width.div_ceil(2);
+    let height = image.height.div_ceil(2);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let (y0, y1) = (2 * y, (2 * y + 1).min(image.height - 1));
+        for x in 0..width {
+            let (x0, x1) = (2 * x, (2 * x + 1).min(image.width - 1));
+            let pixel = |x, y| image.pixels[y * image.width + x];
+            pixels.push(0.25 * (pixel(x0, y0) + pixel(x1, y0) + pixel(x0, y1) + pixel(x1, y1)));
+        }
+    }
+    HdrImage {
+        width,
+        height,
+        pixels,
+    }
+}
+
+// Reads an 8 or 16 bit PNG of any color type. Alpha is ignored.
+fn read_png(path: &Path, srgb: bool) -> io::Result<HdrImage> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    // Turns palettes into RGB and grayscale with less than 8 bits into 8 bits.
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let (info, mut reader) = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut data)?;
+
+    let channels = match info.color_type {
+        png::ColorType::Grayscale => 1,
+        png::ColorType::GrayscaleAlpha => 2,
+        png::ColorType::RGB => 3,
+        png::ColorType::RGBA => 4,
+        png::ColorType::Indexed => unreachable!("palettes are expanded"),
+    };
+    let samples = match info.bit_depth {
+        png::BitDepth::Sixteen => data
+            .chunks_exact(2)
+            .map(|bytes| f64::from(u16::from_be_bytes([bytes[0], bytes[1]])) / 65535.0)
+            .collect::<Vec<_>>(),
+        _ => data.iter().map(|&byte| f64::from(byte) / 255.0).collect(),
+    };
+
+    let decode = |value: f64| if srgb { srgb_to_linear(value) } else { value };
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|pixel| {
+            if channels < 3 {
+                Vec3(pixel[0], pixel[0], pixel[0]).map(decode)
+            } else {
+                Vec3(pixel[0], pixel[1], pixel[2]).map(decode)
+            }
+        })
+        .collect();
+
+    Ok(HdrImage {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
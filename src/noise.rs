@@ -0,0 +1,129 @@
+use crate::Vec3;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+/// Ken Perlin's improved gradient noise ("Improving Noise", 2002), with a permutation that is
+/// shuffled from `seed`, so that the same seed always gives the same noise.
+///
+/// Only the seed is serialized, the permutation is rebuilt on deserialization.
+#[derive(Serialize, Deserialize)]
+#[serde(from = "PerlinData")]
+pub struct Perlin {
+    seed: u64,
+    // The shuffled numbers 0 to 255, twice, so that lookups need no wrapping.
+    #[serde(skip)]
+    permutation: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct PerlinData {
+    seed: u64,
+}
+
+impl From<PerlinData> for Perlin {
+    fn from(data: PerlinData) -> Self {
+        Perlin::new(data.seed)
+    }
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut permutation = (0..=255).collect::<Vec<u8>>();
+        permutation.shuffle(&mut Pcg32::seed_from_u64(seed));
+        permutation.extend_from_within(..);
+        Perlin { seed, permutation }
+    }
+
+    /// Smooth noise at `p`, varying over about one unit. Roughly in [-1, 1], and 0 at integer
+    /// coordinates.
+    pub fn noise(&self, p: Vec3) -> f64 {
+        let cell = p.map(f64::floor);
+        let Vec3(x, y, z) = p - cell;
+        let (u, v, w) = (fade(x), fade(y), fade(z));
+
+        let hash = |i: f64| (i.rem_euclid(256.0)) as usize;
+        let (xi, yi, zi) = (hash(cell.0), hash(cell.1), hash(cell.2));
+        let perm = |i: usize| self.permutation[i] as usize;
+        let a = perm(xi) + yi;
+        let (aa, ab) = (perm(a) + zi, perm(a + 1) + zi);
+        let b = perm(xi + 1) + yi;
+        let (ba, bb) = (perm(b) + zi, perm(b + 1) + zi);
+
+        let corner = |hash: usize, dx: f64, dy: f64, dz: f64| gradient(perm(hash), dx, dy, dz);
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, corner(aa, x, y, z), corner(ba, x - 1.0, y, z)),
+                lerp(
+                    u,
+                    corner(ab, x, y - 1.0, z),
+                    corner(bb, x - 1.0, y - 1.0, z),
+                ),
+            ),
+            lerp(
+                v,
+                lerp(
+                    u,
+                    corner(aa + 1, x, y, z - 1.0),
+                    corner(ba + 1, x - 1.0, y, z - 1.0),
+                ),
+                lerp(
+                    u,
+                    corner(ab + 1, x, y - 1.0, z - 1.0),
+                    corner(bb + 1, x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+
+    /// Fractional Brownian motion: `octaves` layers of noise, each with twice the frequency and
+    /// half the amplitude of the one before. Scaled to stay roughly in [-1, 1].
+    pub fn fbm(&self, p: Vec3, octaves: u32) -> f64 {
+        self.octaves(p, octaves, |noise| noise)
+    }
+
+    /// Like `fbm`, but adding up the absolute values of the layers, which gives creases where
+    /// the noise crosses 0. Roughly in [0, 1].
+    pub fn turbulence(&self, p: Vec3, octaves: u32) -> f64 {
+        self.octaves(p, octaves, f64::abs)
+    }
+
+    fn octaves(&self, p: Vec3, octaves: u32, f: impl Fn(f64) -> f64) -> f64 {
+        let (mut sum, mut total_amplitude) = (0.0, 0.0);
+        let (mut frequency, mut amplitude) = (1.0, 1.0);
+        for _ in 0..octaves.max(1) {
+            sum += amplitude * f(self.noise(frequency * p));
+            total_amplitude += amplitude;
+            frequency *= 2.0;
+            amplitude *= 0.5;
+        }
+        sum / total_amplitude
+    }
+}
+
+// 6t^5 - 15t^4 + 10t^3, which has zero first and second derivatives at 0 and 1.
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// The dot product of (`x`, `y`, `z`) with one of the 12 vectors from the center of a cube to
+// its edges, picked by `hash`.
+fn gradient(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
@@ -0,0 +1,380 @@
+use crate::{Dielectric, DiffuseLight, Hittable, Lambertian, Material, Metal, TriangleMesh, Vec3};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::{FromStr, SplitWhitespace};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, io::Error),
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io(_, e) => Some(e),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+/// Loads a Wavefront OBJ file, together with the MTL material libraries it references.
+///
+/// Returns one `TriangleMesh` per material used in the file. Polygons are triangulated as fans,
+/// object and group statements are ignored. Materials are mapped onto the closest of our own:
+/// emissive ones become `DiffuseLight`, transparent ones `Dielectric`, specular ones `Metal` and
+/// everything else `Lambertian`. Material libraries that cannot be read are skipped with a
+/// warning, and their materials replaced by a default one.
+pub fn load_obj(path: &Path) -> Result<Vec<Box<dyn Hittable>>, ObjError> {
+    let content = read_file(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut positions = Vec::new();
+    let mut uvs = Vec::new();
+    let mut normals = Vec::new();
+    let mut materials = HashMap::new();
+    let mut meshes: Vec<MeshBuilder> = Vec::new();
+    let mut current_mesh: Option<usize> = None;
+    let mut current_material: Option<String> = None;
+
+    for (line_index, line) in content.lines().enumerate() {
+        let error = |message: String| ObjError::Parse {
+            path: path.to_owned(),
+            line: line_index + 1,
+            message,
+        };
+
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => positions.push(parse_vec3(&mut words).map_err(error)?),
+            Some("vt") => {
+                let u = parse_next(&mut words).map_err(error)?;
+                // The v coordinate is optional for 1D textures.
+                let v = words.next().map_or(Ok(0.0), parse_f64).map_err(error)?;
+                uvs.push((u, v));
+            }
+            Some("vn") => normals.push(parse_vec3(&mut words).map_err(error)?),
+            Some("f") => {
+                let mesh = match current_mesh {
+                    Some(mesh) => mesh,
+                    None => {
+                        meshes.push(MeshBuilder::new(current_material.clone()));
+                        meshes.len() - 1
+                    }
+                };
+                current_mesh = Some(mesh);
+
+                let vertices = words
+                    .map(|word| parse_face_vertex(word, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(error)?;
+                if vertices.len() < 3 {
+                    return Err(error(format!("face with only {} vertices", vertices.len())));
+                }
+
+                let mesh = &mut meshes[mesh];
+                let indices = vertices
+                    .into_iter()
+                    .map(|vertex| mesh.add_vertex(vertex, &positions, &uvs, &normals))
+                    .collect::<Vec<_>>();
+                for i in 1..indices.len() - 1 {
+                    mesh.indices.push([indices[0], indices[i], indices[i + 1]]);
+                }
+            }
+            Some("usemtl") => {
+                let name = words.next().ok_or_else(|| error("missing name".into()))?;
+                current_material = Some(name.to_owned());
+                current_mesh = meshes
+                    .iter()
+                    .position(|mesh| mesh.material.as_deref() == Some(name));
+            }
+            Some("mtllib") => {
+                for library in words {
+                    // Models are often passed around without their materials, which then just
+                    // get the default one.
+                    match load_mtl(&directory.join(library), &mut materials) {
+                        Err(ObjError::Io(path, e)) => {
+                            eprintln!("Warning: skipping {}: {}", path.display(), e)
+                        }
+                        result => result?,
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(meshes
+        .into_iter()
+        .filter(|mesh| !mesh.indices.is_empty())
+        .map(|mesh| {
+            let material = mesh
+                .material
+                .as_ref()
+                .and_then(|name| materials.get(name))
+                .map_or_else(MtlMaterial::default, Clone::clone)
+                .to_material();
+            Box::new(mesh.build(material)) as Box<dyn Hittable>
+        })
+        .collect())
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_owned(), e))
+}
+
+fn parse_f64(word: &str) -> Result<f64, String> {
+    f64::from_str(word).map_err(|_| format!("invalid number '{}'", word))
+}
+
+fn parse_next(words: &mut SplitWhitespace) -> Result<f64, String> {
+    words
+        .next()
+        .ok_or_else(|| "missing number".to_owned())
+        .and_then(parse_f64)
+}
+
+fn parse_vec3(words: &mut SplitWhitespace) -> Result<Vec3, String> {
+    Ok(Vec3(
+        parse_next(words)?,
+        parse_next(words)?,
+        parse_next(words)?,
+    ))
+}
+
+// A color of three components, or a single one for a grey.
+fn parse_color(words: &mut SplitWhitespace) -> Result<Vec3, String> {
+    let components = words.map(parse_f64).collect::<Result<Vec<_>, _>>()?;
+    match components.as_slice() {
+        [grey] => Ok(Vec3(*grey, *grey, *grey)),
+        [r, g, b] => Ok(Vec3(*r, *g, *b)),
+        _ => Err(format!(
+            "expected 1 or 3 color components, got {}",
+            components.len()
+        )),
+    }
+}
+
+// Indices into the position, uv and normal lists, already resolved to be 0-based.
+type FaceVertex = (usize, Option<usize>, Option<usize>);
+
+fn parse_face_vertex(
+    word: &str,
+    position_count: usize,
+    uv_count: usize,
+    normal_count: usize,
+) -> Result<FaceVertex, String> {
+    // OBJ indices are 1-based, negative indices count backwards from the last element.
+    let resolve = |index: &str, count: usize| -> Result<usize, String> {
+        let i = i64::from_str(index).map_err(|_| format!("invalid index '{}'", index))?;
+        let resolved = if i < 0 { count as i64 + i } else { i - 1 };
+        if resolved < 0 || resolved >= count as i64 {
+            Err(format!("index {} out of bounds", i))
+        } else {
+            Ok(resolved as usize)
+        }
+    };
+
+    let mut parts = word.split('/');
+    let position = resolve(parts.next().unwrap_or(""), position_count)?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve(index, uv_count)?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(resolve(index, normal_count)?),
+    };
+
+    Ok((position, uv, normal))
+}
+
+struct MeshBuilder {
+    material: Option<String>,
+    vertex_indices: HashMap<FaceVertex, usize>,
+    positions: Vec<Vec3>,
+    uvs: Vec<Option<(f64, f64)>>,
+    normals: Vec<Option<Vec3>>,
+    indices: Vec<[usize; 3]>,
+}
+
+impl MeshBuilder {
+    fn new(material: Option<String>) -> Self {
+        MeshBuilder {
+            material,
+            vertex_indices: HashMap::new(),
+            positions: Vec::new(),
+            uvs: Vec::new(),
+            normals: Vec::new(),
+            indices: Vec::new(),
+        }
+    }
+
+    // OBJ indexes each attribute separately, our meshes share one index between them, so every
+    // distinct combination becomes its own vertex.
+    fn add_vertex(
+        &mut self,
+        vertex: FaceVertex,
+        positions: &[Vec3],
+        uvs: &[(f64, f64)],
+        normals: &[Vec3],
+    ) -> usize {
+        if let Some(&index) = self.vertex_indices.get(&vertex) {
+            return index;
+        }
+
+        let (position, uv, normal) = vertex;
+        let index = self.positions.len();
+        self.positions.push(positions[position]);
+        self.uvs.push(uv.map(|i| uvs[i]));
+        self.normals.push(normal.map(|i| normals[i]));
+        self.vertex_indices.insert(vertex, index);
+        index
+    }
+
+    fn build(self, material: Box<dyn Material>) -> TriangleMesh {
+        // Attributes only some of the vertices have are dropped entirely.
+        let normals = self.normals.into_iter().collect::<Option<Vec<_>>>();
+        let uvs = self.uvs.into_iter().collect::<Option<Vec<_>>>();
+        TriangleMesh::new(
+            self.positions,
+            normals.unwrap_or_default(),
+            uvs.unwrap_or_default(),
+            self.indices,
+            material,
+        )
+    }
+}
+
+#[derive(Clone)]
+struct MtlMaterial {
+    diffuse: Vec3,
+    specular: Vec3,
+    emission: Vec3,
+    specular_exponent: f64,
+    refraction_index: f64,
+    dissolve: f64,
+    illumination_model: u32,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        MtlMaterial {
+            diffuse: Vec3(0.8, 0.8, 0.8),
+            specular: Vec3::ZERO,
+            emission: Vec3::ZERO,
+            specular_exponent: 0.0,
+            refraction_index: 1.5,
+            dissolve: 1.0,
+            illumination_model: 2,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn to_material(&self) -> Box<dyn Material> {
+        let max_component = |v: Vec3| v.0.max(v.1).max(v.2);
+
+        // Illumination models 4, 6 and 7 are the glass/refraction ones, 3 is a mirror.
+        if max_component(self.emission) > 0.0 {
+            Box::new(DiffuseLight {
+                emit: self.emission.into(),
+            })
+        } else if self.dissolve < 1.0 || [4, 6, 7].contains(&self.illumination_model) {
+            Box::new(Dielectric {
+                refraction_index: self.refraction_index,
+                bump_map: None,
+            })
+        } else if self.illumination_model == 3
+            || max_component(self.specular) > max_component(self.diffuse)
+        {
+            // Usual conversion from a Phong exponent to a roughness.
+            let fuzz = (2.0 / (self.specular_exponent + 2.0)).sqrt();
+            // Exporters often write mirrors with only a diffuse color.
+            let albedo = if max_component(self.specular) > 0.0 {
+                self.specular
+            } else {
+                self.diffuse
+            };
+            Box::new(Metal {
+                albedo: albedo.into(),
+                fuzz,
+                bump_map: None,
+            })
+        } else {
+            Box::new(Lambertian {
+                albedo: self.diffuse.into(),
+                bump_map: None,
+            })
+        }
+    }
+}
+
+fn load_mtl(path: &Path, materials: &mut HashMap<String, MtlMaterial>) -> Result<(), ObjError> {
+    let content = read_file(path)?;
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (line_index, line) in content.lines().enumerate() {
+        let error = |message: String| ObjError::Parse {
+            path: path.to_owned(),
+            line: line_index + 1,
+            message,
+        };
+
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+
+        if keyword == "newmtl" {
+            let name = words.next().ok_or_else(|| error("missing name".into()))?;
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((name.to_owned(), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None => continue,
+        };
+        match keyword {
+            "Kd" => material.diffuse = parse_color(&mut words).map_err(error)?,
+            "Ks" => material.specular = parse_color(&mut words).map_err(error)?,
+            "Ke" => material.emission = parse_color(&mut words).map_err(error)?,
+            "Ns" => material.specular_exponent = parse_next(&mut words).map_err(error)?,
+            "Ni" => material.refraction_index = parse_next(&mut words).map_err(error)?,
+            "d" => material.dissolve = parse_next(&mut words).map_err(error)?,
+            "Tr" => material.dissolve = 1.0 - parse_next(&mut words).map_err(error)?,
+            "illum" => material.illumination_model = parse_next(&mut words).map_err(error)? as u32,
+            _ => {}
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use crate::clamp;
+use crate::Film;
+use crate::ToneMapping;
+use crate::Vec3;
+use crate::{write_exr, ExrLayer, ExrPixelType};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// The kinds of image files renders can be saved as.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ImageFormat {
+    // 8-bit, tone mapped.
+    Png,
+    // Linear floating point, see `write_pfm` and `write_exr`.
+    Pfm,
+    Exr,
+}
+
+impl ImageFormat {
+    /// Picks the format from the extension of `path`.
+    pub fn from_path(path: &Path) -> Result<Self, String> {
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("png") => Ok(ImageFormat::Png),
+            Some("pfm") => Ok(ImageFormat::Pfm),
+            Some("exr") => Ok(ImageFormat::Exr),
+            _ => Err(format!(
+                "{}: unsupported image format, expected .png, .pfm or .exr",
+                path.display()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ImageFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pfm => "pfm",
+            ImageFormat::Exr => "exr",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "png" => Ok(ImageFormat::Png),
+            "pfm" => Ok(ImageFormat::Pfm),
+            "exr" => Ok(ImageFormat::Exr),
+            _ => Err(format!(
+                "unknown image format '{}', expected png, pfm or exr",
+                s
+            )),
+        }
+    }
+}
+
+/// Curve applied to linear values when encoding them into an 8-bit image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TransferFunction {
+    // Values are written as they are, like this renderer used to do.
+    Linear,
+    // The piecewise sRGB curve, which is what image viewers assume PNGs without color
+    // information use.
+    Srgb,
+    // A pure power curve, x^(1/gamma).
+    Gamma(f64),
+}
+
+impl TransferFunction {
+    /// Maps a linear value in [0, 1] to its encoded value in [0, 1].
+    pub fn encode(self, x: f64) -> f64 {
+        match self {
+            TransferFunction::Linear => x,
+            TransferFunction::Srgb => {
+                if x <= 0.003_130_8 {
+                    12.92 * x
+                } else {
+                    1.055 * x.powf(1.0 / 2.4) - 0.055
+                }
+            }
+            TransferFunction::Gamma(gamma) => x.powf(1.0 / gamma),
+        }
+    }
+}
+
+impl fmt::Display for TransferFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferFunction::Linear => write!(f, "linear"),
+            TransferFunction::Srgb => write!(f, "srgb"),
+            TransferFunction::Gamma(gamma) => write!(f, "gamma:{}", gamma),
+        }
+    }
+}
+
+// Parses "linear", "srgb" or "gamma:<gamma>".
+impl FromStr for TransferFunction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "linear" => Ok(TransferFunction::Linear),
+            "srgb" => Ok(TransferFunction::Srgb),
+            _ => match s.strip_prefix("gamma:").map(f64::from_str) {
+                Some(Ok(gamma)) if gamma > 0.0 => Ok(TransferFunction::Gamma(gamma)),
+                _ => Err(format!(
+                    "unknown transfer function '{}', expected linear, srgb or gamma:<gamma>",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// Tone maps the film and converts it to 8-bit RGB values, row by row.
+pub fn encode_rgb8(film: &Film, tone_mapping: &ToneMapping, transfer: TransferFunction) -> Vec<u8> {
+    film.pixels
+        .iter()
+        .flat_map(|&c| tone_mapping.apply(c))
+        .map(|c| (255.0 * transfer.encode(c)).round() as u8)
+        .collect()
+}
+
+pub fn write_png(
+    path: &Path,
+    film: &Film,
+    tone_mapping: &ToneMapping,
+    transfer: TransferFunction,
+) -> io::Result<()> {
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, film.width, film.height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&encode_rgb8(film, tone_mapping, transfer))?;
+    Ok(())
+}
+
+/// Writes the film to `path` in the given format. The AOVs of the film become
+/// layers of EXR files, and are written to separate `<name>.<aov>.pfm` files next to the image
+/// otherwise. Only PNGs are tone mapped.
+pub fn write_image(
+    path: &Path,
+    film: &Film,
+    format: ImageFormat,
+    tone_mapping: &ToneMapping,
+    transfer: TransferFunction,
+    exr_pixel_type: ExrPixelType,
+) -> io::Result<()> {
+    if format == ImageFormat::Exr {
+        let mut layers = vec![ExrLayer {
+            name: "",
+            channels: &["R", "G", "B"],
+            pixels: &film.pixels,
+        }];
+        layers.extend(film.aovs.iter().map(|(aov, pixels)| ExrLayer {
+            name: aov.name(),
+            channels: aov.channels(),
+            pixels,
+        }));
+        return write_exr(path, film.width, film.height, &layers, exr_pixel_type);
+    }
+
+    match format {
+        ImageFormat::Png => write_png(path, film, tone_mapping, transfer)?,
+        _ => write_pfm(path, film.width, film.height, &film.pixels, false)?,
+    }
+    for (aov, pixels) in &film.aovs {
+        let aov_path = path.with_extension(format!("{}.pfm", aov.name()));
+        let grayscale = aov.channels().len() == 1;
+        write_pfm(&aov_path, film.width, film.height, pixels, grayscale)?;
+    }
+    Ok(())
+}
+
+/// Writes the pixels as a little-endian Portable Float Map. Grayscale maps only store the first
+/// component.
+pub fn write_pfm(
+    path: &Path,
+    width: u32,
+    height: u32,
+    pixels: &[Vec3],
+    grayscale: bool,
+) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    // A negative scale means little-endian.
+    let kind = if grayscale { "Pf" } else { "PF" };
+    write!(out, "{}\n{} {}\n-1.0\n", kind, width, height)?;
+    let components = if grayscale { 1 } else { 3 };
+    // PFM stores the bottom row first.
+    for row in pixels.chunks(width as usize).rev() {
+        for pixel in row {
+            for c in pixel.into_iter().take(components) {
+                out.write_all(&(c as f32).to_le_bytes())?;
+            }
+        }
+    }
+    out.flush()
+}
+
+/// Writes the first component of each pixel as a heatmap PNG, going from dark blue for 0 over
+/// green and yellow to red for `max`.
+pub fn write_heatmap(
+    path: &Path,
+    width: u32,
+    height: u32,
+    values: &[Vec3],
+    max: f64,
+) -> io::Result<()> {
+    const COLORS: [Vec3; 5] = [
+        Vec3(0.0, 0.0, 0.5),
+        Vec3(0.0, 0.4, 1.0),
+        Vec3(0.2, 0.9, 0.2),
+        Vec3(1.0, 0.9, 0.0),
+        Vec3(1.0, 0.1, 0.0),
+    ];
+
+    let data = values
+        .iter()
+        .flat_map(|value| {
+            let t = clamp(value.0 / max, 0.0, 1.0) * (COLORS.len() - 1) as f64;
+            let i = (t as usize).min(COLORS.len() - 2);
+            let t = t - i as f64;
+            (1.0 - t) * COLORS[i] + t * COLORS[i + 1]
+        })
+        .map(|c| (255.0 * c).round() as u8)
+        .collect::<Vec<_>>();
+
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width, height);
+    encoder.set_color(png::ColorType::RGB);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    Ok(())
+}
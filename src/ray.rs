@@ -4,11 +4,18 @@ use crate::Vec3;
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // How fast the area the ray stands for widens with distance, as an angle, e.g. the angle a
+    // pixel covers for camera rays. 0 if unknown.
+    pub spread: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            spread: 0.0,
+        }
     }
 
     pub fn at(&self, t: f64) -> Vec3 {
@@ -0,0 +1,327 @@
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Supplies the "random" numbers in [0, 1) for the samples of a pixel. Each sample asks for
+/// the same sequence of dimensions (two for the position in the pixel, two for the lens, then
+/// whatever the integrator, materials and lights need), and better samplers spread the values
+/// for each dimension more evenly over the pixel's samples than independent random numbers.
+pub trait Sampler {
+    /// Moves on to the sample with the given index, restarting at the first dimension.
+    fn start_sample(&mut self, index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// Selects the sampler used by `render`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SamplerKind {
+    // Plain random numbers.
+    Independent,
+    // One jittered sample in each of a randomly shuffled grid of strata.
+    Stratified,
+    // The Halton sequence, randomized per pixel with Cranley-Patterson rotations.
+    Halton,
+    // The Sobol (0, 2)-sequence for each pair of dimensions, with hash-based Owen scrambling
+    // and shuffling.
+    Sobol,
+}
+
+impl SamplerKind {
+    /// A sampler for one pixel, with a `seed` that is unique to the pixel. `samples_per_pixel`
+    /// is how many samples the pixel will (at most) get.
+    pub fn build(self, seed: u64, samples_per_pixel: u32) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler {
+                rng: Pcg32::seed_from_u64(seed),
+            }),
+            SamplerKind::Stratified => Box::new(StratifiedSampler {
+                seed,
+                samples_per_pixel: samples_per_pixel.max(1),
+                index: 0,
+                dimension: 0,
+            }),
+            SamplerKind::Halton => Box::new(HaltonSampler {
+                seed,
+                index: 0,
+                dimension: 0,
+            }),
+            SamplerKind::Sobol => Box::new(SobolSampler {
+                seed,
+                index: 0,
+                dimension: 0,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SamplerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SamplerKind::Independent => "independent",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for SamplerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "independent" => Ok(SamplerKind::Independent),
+            "stratified" => Ok(SamplerKind::Stratified),
+            "halton" => Ok(SamplerKind::Halton),
+            "sobol" => Ok(SamplerKind::Sobol),
+            _ => Err(format!(
+                "unknown sampler '{}', expected independent, stratified, halton or sobol",
+                s
+            )),
+        }
+    }
+}
+
+pub struct IndependentSampler {
+    rng: Pcg32,
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, _index: u32) {}
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+/// Splits each dimension (or each pair of dimensions, into a grid) into as many strata as there
+/// are samples, and places each sample randomly in a different one. Every dimension assigns
+/// the strata to samples in a different order, so the dimensions are not correlated.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: u32,
+    index: u32,
+    dimension: u32,
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = next(&mut self.dimension);
+        let strata = self.samples_per_pixel;
+        if self.index >= strata {
+            return to_unit(hash(&[self.seed, dimension, u64::from(self.index)]));
+        }
+
+        let stratum = permute(self.index, strata, hash(&[self.seed, dimension]));
+        let jitter = to_unit(hash(&[self.seed, dimension, u64::from(self.index)]));
+        (f64::from(stratum) + jitter) / f64::from(strata)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = next(&mut self.dimension);
+        // A grid with at least as many cells as samples. If there are more, some are empty.
+        let columns = f64::from(self.samples_per_pixel).sqrt().ceil() as u32;
+        let rows = self.samples_per_pixel.div_ceil(columns);
+        let jitter_x = to_unit(hash(&[self.seed, dimension, u64::from(self.index), 0]));
+        let jitter_y = to_unit(hash(&[self.seed, dimension, u64::from(self.index), 1]));
+        if self.index >= columns * rows {
+            return (jitter_x, jitter_y);
+        }
+
+        let cell = permute(self.index, columns * rows, hash(&[self.seed, dimension]));
+        (
+            (f64::from(cell % columns) + jitter_x) / f64::from(columns),
+            (f64::from(cell / columns) + jitter_y) / f64::from(rows),
+        )
+    }
+}
+
+// Bases of the Halton sequence's dimensions. Dimensions beyond these get random numbers.
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131,
+];
+
+/// The radical inverse of the sample index in a different prime base for each dimension. All
+/// pixels use the same points, but shifted (modulo 1) by a random offset per pixel and
+/// dimension.
+pub struct HaltonSampler {
+    seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = next(&mut self.dimension);
+        let offset = to_unit(hash(&[self.seed, dimension]));
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => (radical_inverse(base, self.index) + offset).fract(),
+            None => to_unit(hash(&[self.seed, dimension, u64::from(self.index)])),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// "Practical Hash-based Owen Scrambling" (Burley, 2020): every pair of dimensions uses the
+/// first two dimensions of the Sobol sequence, which are well distributed in 2D, Owen scrambled
+/// and with the sample order shuffled differently for each pair, so pairs are not correlated
+/// with each other.
+pub struct SobolSampler {
+    seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl SobolSampler {
+    // The shuffled index for the current sample in `dimension`.
+    fn shuffled_index(&self, dimension: u64) -> u32 {
+        nested_uniform_scramble(self.index, hash(&[self.seed, dimension, 0]) as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, index: u32) {
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = next(&mut self.dimension);
+        let index = self.shuffled_index(dimension);
+        let x = nested_uniform_scramble(
+            index.reverse_bits(),
+            hash(&[self.seed, dimension, 1]) as u32,
+        );
+        to_unit(u64::from(x) << 32)
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let dimension = next(&mut self.dimension);
+        let index = self.shuffled_index(dimension);
+        let x = nested_uniform_scramble(
+            index.reverse_bits(),
+            hash(&[self.seed, dimension, 1]) as u32,
+        );
+        let y = nested_uniform_scramble(sobol_1(index), hash(&[self.seed, dimension, 2]) as u32);
+        (to_unit(u64::from(x) << 32), to_unit(u64::from(y) << 32))
+    }
+}
+
+// Returns the current dimension, and moves on to the next one.
+fn next(dimension: &mut u32) -> u64 {
+    *dimension += 1;
+    u64::from(*dimension - 1)
+}
+
+// The second dimension of the Sobol sequence, the first one is just the reversed bits of the
+// index.
+fn sobol_1(index: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1 << 31;
+    for bit in 0..32 {
+        if index & (1 << bit) != 0 {
+            result ^= direction;
+        }
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+fn radical_inverse(base: u32, mut index: u32) -> f64 {
+    let inverse_base = 1.0 / f64::from(base);
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    while index > 0 {
+        result += f64::from(index % base) * factor;
+        index /= base;
+        factor *= inverse_base;
+    }
+    result
+}
+
+// Owen scrambling of the bits of `x`, from Burley's paper, using the improved hash from
+// https://psychopath.io/post/2021_01_30_building_a_better_lk_hash.
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x ^= x.wrapping_mul(0x3d20_adea);
+    x = x.wrapping_add(seed);
+    x = x.wrapping_mul((seed >> 16) | 1);
+    x ^= x.wrapping_mul(0x0552_6c56);
+    x ^= x.wrapping_mul(0x53a2_2864);
+    x.reverse_bits()
+}
+
+// The position of `i` in a random permutation of 0..length chosen by `seed`, from "Correlated
+// Multi-Jittered Sampling" (Kensler, 2013).
+fn permute(mut i: u32, length: u32, seed: u64) -> u32 {
+    let p = seed as u32;
+    let mut w = length - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < length {
+            break;
+        }
+    }
+    ((u64::from(i) + u64::from(p)) % u64::from(length)) as u32
+}
+
+// Mixes the values into one well distributed hash, with the finalizer of SplitMix64.
+fn hash(values: &[u64]) -> u64 {
+    values.iter().fold(0x9e37_79b9_7f4a_7c15, |hash, &value| {
+        let mut z = (hash ^ value).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    })
+}
+
+// Maps the upper 53 bits of a hash to [0, 1).
+fn to_unit(hash: u64) -> f64 {
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
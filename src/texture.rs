@@ -0,0 +1,241 @@
+use crate::mipmap::MipMap;
+use crate::noise::Perlin;
+use crate::Hit;
+use crate::Vec3;
+use crate::{canonical_path, resolve_scene_path, serialize_scene_path};
+use crate::{AddressMode, TextureFilter};
+use serde::de::value::{MapAccessDeserializer, SeqAccessDeserializer};
+use serde::de::{MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// A color that varies over surfaces, used for the parameters of materials.
+#[typetag::serde]
+pub trait Texture: Sync {
+    fn value(&self, hit: &Hit) -> Vec3;
+}
+
+impl From<Vec3> for Box<dyn Texture> {
+    fn from(color: Vec3) -> Self {
+        Box::new(ConstantTexture { color })
+    }
+}
+
+// Materials used to store plain colors, which scene files can still use wherever a texture is
+// expected. Colors are sequences and textures are maps with the type's name as the key.
+pub(crate) fn deserialize<'de, D>(deserializer: D) -> Result<Box<dyn Texture>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct TextureVisitor;
+
+    impl<'de> Visitor<'de> for TextureVisitor {
+        type Value = Box<dyn Texture>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a texture or a color")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+            Vec3::deserialize(SeqAccessDeserializer::new(seq)).map(Into::into)
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            Box::<dyn Texture>::deserialize(MapAccessDeserializer::new(map))
+        }
+    }
+
+    deserializer.deserialize_any(TextureVisitor)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ConstantTexture {
+    pub color: Vec3,
+}
+
+#[typetag::serde]
+impl Texture for ConstantTexture {
+    fn value(&self, _: &Hit) -> Vec3 {
+        self.color
+    }
+}
+
+/// A 3D checkerboard of cubes with edges `size` long, alternating between two textures.
+#[derive(Serialize, Deserialize)]
+pub struct CheckerTexture {
+    #[serde(deserialize_with = "deserialize")]
+    pub even: Box<dyn Texture>,
+    #[serde(deserialize_with = "deserialize")]
+    pub odd: Box<dyn Texture>,
+    pub size: f64,
+}
+
+#[typetag::serde]
+impl Texture for CheckerTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let cell = (hit.point / self.size).map(f64::floor);
+        if (cell.0 + cell.1 + cell.2) as i64 % 2 == 0 {
+            self.even.value(hit)
+        } else {
+            self.odd.value(hit)
+        }
+    }
+}
+
+/// A texture from a PNG file, mapped onto surfaces with their texture coordinates (with v
+/// pointing up in the image). The scene file only stores the path, which is relative to the
+/// scene file (`new` takes it relative to the current directory), and textures with the same
+/// path share the image.
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "ImageTextureData")]
+pub struct ImageTexture {
+    #[serde(serialize_with = "serialize_scene_path")]
+    path: PathBuf,
+    // Whether the file stores sRGB encoded colors, like most color images do, rather than linear
+    // values, like normal maps.
+    srgb: bool,
+    address: AddressMode,
+    filter: TextureFilter,
+    #[serde(skip)]
+    image: Arc<MipMap>,
+}
+
+#[derive(Deserialize)]
+struct ImageTextureData {
+    path: PathBuf,
+    #[serde(default = "default_srgb")]
+    srgb: bool,
+    #[serde(default = "default_address")]
+    address: AddressMode,
+    #[serde(default = "default_filter")]
+    filter: TextureFilter,
+}
+
+fn default_srgb() -> bool {
+    true
+}
+
+fn default_address() -> AddressMode {
+    AddressMode::Wrap
+}
+
+fn default_filter() -> TextureFilter {
+    TextureFilter::Trilinear
+}
+
+impl TryFrom<ImageTextureData> for ImageTexture {
+    type Error = String;
+
+    fn try_from(data: ImageTextureData) -> Result<Self, Self::Error> {
+        let path = resolve_scene_path(&data.path);
+        ImageTexture::new(path.clone(), data.srgb, data.address, data.filter)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl ImageTexture {
+    pub fn new(
+        path: PathBuf,
+        srgb: bool,
+        address: AddressMode,
+        filter: TextureFilter,
+    ) -> io::Result<Self> {
+        let image = MipMap::load(&path, srgb)?;
+        Ok(ImageTexture {
+            path: canonical_path(&path)?,
+            srgb,
+            address,
+            filter,
+            image,
+        })
+    }
+}
+
+#[typetag::serde]
+impl Texture for ImageTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let (u, v) = hit.uv;
+        // How many pixels of the image the hit's footprint covers, along the wider direction.
+        let width = (hit.footprint / hit.dpdu.mag() * self.image.width() as f64)
+            .max(hit.footprint / hit.dpdv.mag() * self.image.height() as f64);
+        self.image
+            .lookup(u, 1.0 - v, width, self.filter, self.address)
+    }
+}
+
+/// Fractal noise at `scale` times the world space position, blending from `low` to `high` as
+/// the noise goes from -1 to 1. Good for clouds, rust or rough terrain.
+#[derive(Serialize, Deserialize)]
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: u32,
+    #[serde(deserialize_with = "deserialize")]
+    pub low: Box<dyn Texture>,
+    #[serde(deserialize_with = "deserialize")]
+    pub high: Box<dyn Texture>,
+}
+
+#[typetag::serde]
+impl Texture for NoiseTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let t = 0.5 * (1.0 + self.noise.fbm(self.scale * hit.point, self.octaves));
+        blend(t, &*self.low, &*self.high, hit)
+    }
+}
+
+/// Marble: stripes across the x axis, 2π / `scale` apart, that are distorted by turbulence.
+/// Higher `turbulence` gives more swirly veins. The stripes blend between `low` and `high`.
+#[derive(Serialize, Deserialize)]
+pub struct MarbleTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: u32,
+    pub turbulence: f64,
+    #[serde(deserialize_with = "deserialize")]
+    pub low: Box<dyn Texture>,
+    #[serde(deserialize_with = "deserialize")]
+    pub high: Box<dyn Texture>,
+}
+
+#[typetag::serde]
+impl Texture for MarbleTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let p = self.scale * hit.point;
+        let phase = p.0 + self.turbulence * self.noise.turbulence(p, self.octaves);
+        blend(0.5 * (1.0 + phase.sin()), &*self.low, &*self.high, hit)
+    }
+}
+
+/// Wood: rings around the y axis, `1 / scale` apart, that are bent by fractal noise. Higher
+/// `turbulence` gives more irregular rings. Each ring goes from `low` to `high`.
+#[derive(Serialize, Deserialize)]
+pub struct WoodTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub octaves: u32,
+    pub turbulence: f64,
+    #[serde(deserialize_with = "deserialize")]
+    pub low: Box<dyn Texture>,
+    #[serde(deserialize_with = "deserialize")]
+    pub high: Box<dyn Texture>,
+}
+
+#[typetag::serde]
+impl Texture for WoodTexture {
+    fn value(&self, hit: &Hit) -> Vec3 {
+        let p = self.scale * hit.point;
+        let radius = (p.0 * p.0 + p.2 * p.2).sqrt();
+        let rings = radius + self.turbulence * self.noise.fbm(p, self.octaves);
+        blend(rings - rings.floor(), &*self.low, &*self.high, hit)
+    }
+}
+
+// Blends linearly from `low` at t = 0 to `high` at t = 1.
+fn blend(t: f64, low: &dyn Texture, high: &dyn Texture, hit: &Hit) -> Vec3 {
+    (1.0 - t) * low.value(hit) + t * high.value(hit)
+}
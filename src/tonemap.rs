@@ -0,0 +1,127 @@
+use crate::clamp;
+use crate::Vec3;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Curve that compresses the unbounded linear colors of a render into [0, 1].
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ToneMapOperator {
+    // Cuts everything above the white point off.
+    Clamp,
+    // L / (1 + L) on the luminance, from "Photographic Tone Reproduction for Digital Images"
+    // (Reinhard et al., 2002). Never quite reaches white.
+    Reinhard,
+    // Reinhard's variant that maps the white point to exactly 1.
+    ReinhardExtended,
+    // Krzysztof Narkowicz's fit of the ACES filmic reference rendering transform.
+    Aces,
+    // John Hable's filmic curve from Uncharted 2.
+    Hable,
+}
+
+impl fmt::Display for ToneMapOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ToneMapOperator::Clamp => "clamp",
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::ReinhardExtended => "reinhard-extended",
+            ToneMapOperator::Aces => "aces",
+            ToneMapOperator::Hable => "hable",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(ToneMapOperator::Clamp),
+            "reinhard" => Ok(ToneMapOperator::Reinhard),
+            "reinhard-extended" => Ok(ToneMapOperator::ReinhardExtended),
+            "aces" => Ok(ToneMapOperator::Aces),
+            "hable" => Ok(ToneMapOperator::Hable),
+            _ => Err(format!(
+                "unknown tone mapping operator '{}', expected clamp, reinhard, \
+                 reinhard-extended, aces or hable",
+                s
+            )),
+        }
+    }
+}
+
+/// Everything that happens between the linear film and the transfer function of the output.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapping {
+    pub operator: ToneMapOperator,
+    // In stops, so each +1 doubles the brightness before tone mapping.
+    pub exposure: f64,
+    // The (exposed) value that ends up as white, for clamp, extended Reinhard and Hable. If not
+    // set, 1, 4 and 11.2 respectively.
+    pub white_point: Option<f64>,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            operator: ToneMapOperator::Clamp,
+            exposure: 0.0,
+            white_point: None,
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Maps a linear color to [0, 1].
+    pub fn apply(&self, color: Vec3) -> Vec3 {
+        let color = color * 2f64.powf(self.exposure);
+        let white = |default| self.white_point.unwrap_or(default);
+
+        match self.operator {
+            ToneMapOperator::Clamp => color / white(1.0),
+            ToneMapOperator::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapOperator::ReinhardExtended => {
+                let white = white(4.0);
+                scale_luminance(color, |l| l * (1.0 + l / (white * white)) / (1.0 + l))
+            }
+            ToneMapOperator::Aces => color.map(aces),
+            ToneMapOperator::Hable => {
+                let white = white(11.2);
+                color.map(|c| hable(c) / hable(white))
+            }
+        }
+        // The luminance based operators can push single channels of saturated colors above 1.
+        .map(|c| clamp(c, 0.0, 1.0))
+    }
+}
+
+// Scales the color so that its luminance becomes `f(luminance)`, which keeps its hue.
+fn scale_luminance(color: Vec3, f: impl Fn(f64) -> f64) -> Vec3 {
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return Vec3::ZERO;
+    }
+    color * (f(luminance) / luminance)
+}
+
+// https://knarkowicz.wordpress.com/2016/01/06/aces-filmic-tone-mapping-curve/, with the input
+// scaled by 0.6 like the post suggests to match the reference transform's brightness.
+fn aces(x: f64) -> f64 {
+    let x = 0.6 * x.max(0.0);
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+// http://filmicworlds.com/blog/filmic-tonemapping-operators/
+fn hable(x: f64) -> f64 {
+    const A: f64 = 0.15;
+    const B: f64 = 0.50;
+    const C: f64 = 0.10;
+    const D: f64 = 0.20;
+    const E: f64 = 0.02;
+    const F: f64 = 0.30;
+    let x = x.max(0.0);
+    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+}
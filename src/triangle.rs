@@ -0,0 +1,393 @@
+use crate::bvh::BvhTree;
+use crate::Aabb;
+use crate::Hit;
+use crate::Hittable;
+use crate::Material;
+use crate::Ray;
+use crate::Sampler;
+use crate::Vec3;
+use crate::T_MIN;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::ops::Range;
+
+// Möller-Trumbore ray-triangle intersection. Returns the ray parameter and the barycentric
+// coordinates of the hit with respect to `p1` and `p2`.
+fn intersect(
+    ray: &Ray,
+    p0: Vec3,
+    p1: Vec3,
+    p2: Vec3,
+    t_range: Range<f64>,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = p1 - p0;
+    let edge2 = p2 - p0;
+    let h = ray.direction.cross(edge2);
+    let det = edge1.dot(h);
+    if det.abs() < 1e-12 {
+        // Ray is parallel to the triangle (or the triangle is degenerate).
+        return None;
+    }
+
+    let inverse_det = 1.0 / det;
+    let s = ray.origin - p0;
+    let b1 = inverse_det * s.dot(h);
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+
+    let q = s.cross(edge1);
+    let b2 = inverse_det * ray.direction.dot(q);
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+
+    let t = inverse_det * edge2.dot(q);
+    if t_range.contains(&t) {
+        Some((t, b1, b2))
+    } else {
+        None
+    }
+}
+
+// Uniformly distributed point on a triangle, from two uniform random numbers.
+fn sample_triangle(p0: Vec3, p1: Vec3, p2: Vec3, (u1, u2): (f64, f64)) -> Vec3 {
+    let su1 = u1.sqrt();
+    let b0 = 1.0 - su1;
+    let b1 = u2 * su1;
+    b0 * p0 + b1 * p1 + (1.0 - b0 - b1) * p2
+}
+
+// Converts a density over the area of a light into one over solid angle as seen from `origin`,
+// for a ray from `origin` in `direction` hitting the light at `t`.
+fn area_to_solid_angle_pdf(direction: Vec3, t: f64, normal: Vec3, area_pdf: f64) -> f64 {
+    let distance_squared = t * t * direction.mag_squared();
+    let cosine = direction.dot(normal).abs() / direction.mag();
+    if cosine <= 0.0 {
+        return 0.0;
+    }
+    area_pdf * distance_squared / cosine
+}
+
+// The derivatives of the position on a triangle with respect to its texture coordinates. None
+// if the texture coordinates are degenerate.
+fn uv_derivatives(p: [Vec3; 3], uv: [(f64, f64); 3]) -> Option<(Vec3, Vec3)> {
+    let (du02, dv02) = (uv[0].0 - uv[2].0, uv[0].1 - uv[2].1);
+    let (du12, dv12) = (uv[1].0 - uv[2].0, uv[1].1 - uv[2].1);
+    let (dp02, dp12) = (p[0] - p[2], p[1] - p[2]);
+    let det = du02 * dv12 - dv02 * du12;
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let dpdu = (dv12 * dp02 - dv02 * dp12) / det;
+    let dpdv = (du02 * dp12 - du12 * dp02) / det;
+    Some((dpdu, dpdv))
+}
+
+fn triangle_bounding_box(p0: Vec3, p1: Vec3, p2: Vec3) -> Aabb {
+    Aabb::new(p0.min(p1).min(p2), p0.max(p1).max(p2))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Triangle {
+    // The outward normal is (v1 - v0) x (v2 - v0).
+    pub vertices: [Vec3; 3],
+    pub material: Box<dyn Material>,
+}
+
+#[typetag::serde]
+impl Hittable for Triangle {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<Hit<'_>> {
+        let [p0, p1, p2] = self.vertices;
+        let (t, b1, b2) = intersect(&ray, p0, p1, p2, t_range)?;
+        let normal = (p1 - p0).cross(p2 - p0).normalized();
+        // The barycentric coordinates double as texture coordinates.
+        let hit = Hit::new(ray.at(t), normal, t, &ray, self.material.as_ref(), self);
+        Some(hit.with_uv((b1, b2), p1 - p0, p2 - p0))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let [p0, p1, p2] = self.vertices;
+        Some(triangle_bounding_box(p0, p1, p2))
+    }
+
+    fn collect_objects<'a>(&'a self, objects: &mut Vec<&'a dyn Hittable>) {
+        objects.push(self);
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<&'a dyn Hittable>) {
+        if self.material.is_emissive() {
+            lights.push(self);
+        }
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        let [p0, p1, p2] = self.vertices;
+        let ray = Ray::new(origin, direction);
+        match intersect(&ray, p0, p1, p2, T_MIN..f64::INFINITY) {
+            Some((t, _, _)) => {
+                let cross = (p1 - p0).cross(p2 - p0);
+                let area = 0.5 * cross.mag();
+                area_to_solid_angle_pdf(direction, t, cross / (2.0 * area), 1.0 / area)
+            }
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        let [p0, p1, p2] = self.vertices;
+        sample_triangle(p0, p1, p2, sampler.get_2d()) - origin
+    }
+}
+
+/// An indexed triangle mesh with a single material.
+///
+/// Vertex attributes are stored in shared buffers, each triangle refers to three entries by
+/// index. `normals` and `uvs` are optional; if present, they have one entry per position. With
+/// per-vertex normals, the mesh is smooth shaded by interpolating them across each triangle.
+#[derive(Serialize, Deserialize)]
+#[serde(try_from = "TriangleMeshData")]
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    normals: Vec<Vec3>,
+    uvs: Vec<(f64, f64)>,
+    indices: Vec<[usize; 3]>,
+    material: Box<dyn Material>,
+    // Built over the triangles, which are reordered to match the tree.
+    #[serde(skip)]
+    tree: BvhTree,
+    // Running sum of the triangle areas, for sampling the mesh as a light.
+    #[serde(skip)]
+    area_cdf: Vec<f64>,
+}
+
+#[derive(Deserialize)]
+struct TriangleMeshData {
+    positions: Vec<Vec3>,
+    #[serde(default)]
+    normals: Vec<Vec3>,
+    #[serde(default)]
+    uvs: Vec<(f64, f64)>,
+    indices: Vec<[usize; 3]>,
+    material: Box<dyn Material>,
+}
+
+impl TryFrom<TriangleMeshData> for TriangleMesh {
+    type Error = String;
+
+    fn try_from(data: TriangleMeshData) -> Result<Self, Self::Error> {
+        validate(&data.positions, &data.normals, &data.uvs, &data.indices)?;
+        Ok(TriangleMesh::new(
+            data.positions,
+            data.normals,
+            data.uvs,
+            data.indices,
+            data.material,
+        ))
+    }
+}
+
+fn validate(
+    positions: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[(f64, f64)],
+    indices: &[[usize; 3]],
+) -> Result<(), String> {
+    if !normals.is_empty() && normals.len() != positions.len() {
+        return Err(format!(
+            "mesh has {} normals but {} positions",
+            normals.len(),
+            positions.len()
+        ));
+    }
+    if !uvs.is_empty() && uvs.len() != positions.len() {
+        return Err(format!(
+            "mesh has {} uvs but {} positions",
+            uvs.len(),
+            positions.len()
+        ));
+    }
+    if let Some(index) = indices.iter().flatten().find(|&&i| i >= positions.len()) {
+        return Err(format!(
+            "mesh index {} out of bounds for {} positions",
+            index,
+            positions.len()
+        ));
+    }
+    Ok(())
+}
+
+impl TriangleMesh {
+    /// Panics if the attribute buffers have mismatching lengths or an index is out of bounds.
+    pub fn new(
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        uvs: Vec<(f64, f64)>,
+        indices: Vec<[usize; 3]>,
+        material: Box<dyn Material>,
+    ) -> Self {
+        if let Err(e) = validate(&positions, &normals, &uvs, &indices) {
+            panic!("invalid triangle mesh: {}", e);
+        }
+
+        let bboxes = indices
+            .iter()
+            .map(|&[i0, i1, i2]| triangle_bounding_box(positions[i0], positions[i1], positions[i2]))
+            .collect::<Vec<_>>();
+        let (tree, order) = BvhTree::build(&bboxes);
+        let indices = order.into_iter().map(|i| indices[i]).collect::<Vec<_>>();
+
+        let mut total_area = 0.0;
+        let area_cdf = indices
+            .iter()
+            .map(|&[i0, i1, i2]| {
+                let (p0, p1, p2) = (positions[i0], positions[i1], positions[i2]);
+                total_area += 0.5 * (p1 - p0).cross(p2 - p0).mag();
+                total_area
+            })
+            .collect();
+
+        TriangleMesh {
+            positions,
+            normals,
+            uvs,
+            indices,
+            material,
+            tree,
+            area_cdf,
+        }
+    }
+
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    pub fn uvs(&self) -> &[(f64, f64)] {
+        &self.uvs
+    }
+
+    pub fn indices(&self) -> &[[usize; 3]] {
+        &self.indices
+    }
+
+    pub fn material(&self) -> &dyn Material {
+        self.material.as_ref()
+    }
+
+    fn total_area(&self) -> f64 {
+        self.area_cdf.last().copied().unwrap_or(0.0)
+    }
+
+    // With `smooth` false, the hit always has the geometric normal of the triangle.
+    fn hit_triangle(
+        &self,
+        index: usize,
+        ray: &Ray,
+        t_range: Range<f64>,
+        smooth: bool,
+    ) -> Option<Hit<'_>> {
+        let [i0, i1, i2] = self.indices[index];
+        let (p0, p1, p2) = (self.positions[i0], self.positions[i1], self.positions[i2]);
+        let (t, b1, b2) = intersect(ray, p0, p1, p2, t_range)?;
+
+        let b0 = 1.0 - b1 - b2;
+        let geometric_normal = (p1 - p0).cross(p2 - p0).normalized();
+        let hit = Hit::new(
+            ray.at(t),
+            geometric_normal,
+            t,
+            ray,
+            self.material.as_ref(),
+            self,
+        );
+        let hit = match self.triangle_uvs(index) {
+            Some(uvs) => {
+                let uv = (
+                    b0 * uvs[0].0 + b1 * uvs[1].0 + b2 * uvs[2].0,
+                    b0 * uvs[0].1 + b1 * uvs[1].1 + b2 * uvs[2].1,
+                );
+                let (dpdu, dpdv) = uv_derivatives([p0, p1, p2], uvs)
+                    .unwrap_or_else(|| geometric_normal.orthonormal_basis());
+                hit.with_uv(uv, dpdu, dpdv)
+            }
+            None => hit.with_uv((b1, b2), p1 - p0, p2 - p0),
+        };
+
+        if !smooth || self.normals.is_empty() {
+            return Some(hit);
+        }
+        let shading_normal =
+            (b0 * self.normals[i0] + b1 * self.normals[i1] + b2 * self.normals[i2]).normalized();
+        // Keep the shading normal on the same side as the triangle's winding order.
+        let shading_normal = if shading_normal.dot(geometric_normal) < 0.0 {
+            -shading_normal
+        } else {
+            shading_normal
+        };
+        Some(hit.with_shading_normal(shading_normal))
+    }
+
+    fn triangle_uvs(&self, index: usize) -> Option<[(f64, f64); 3]> {
+        if self.uvs.is_empty() {
+            return None;
+        }
+        let [i0, i1, i2] = self.indices[index];
+        Some([self.uvs[i0], self.uvs[i1], self.uvs[i2]])
+    }
+}
+
+#[typetag::serde]
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: Ray, t_range: Range<f64>) -> Option<Hit<'_>> {
+        self.tree.hit(ray, t_range, |i, t_range| {
+            self.hit_triangle(i, &ray, t_range, true)
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.tree.bounding_box()
+    }
+
+    fn collect_objects<'a>(&'a self, objects: &mut Vec<&'a dyn Hittable>) {
+        objects.push(self);
+    }
+
+    fn collect_lights<'a>(&'a self, lights: &mut Vec<&'a dyn Hittable>) {
+        if self.material.is_emissive() && self.total_area() > 0.0 {
+            lights.push(self);
+        }
+    }
+
+    fn pdf_value(&self, origin: Vec3, direction: Vec3) -> f64 {
+        let ray = Ray::new(origin, direction);
+        let hit = self.tree.hit(ray, T_MIN..f64::INFINITY, |i, t_range| {
+            self.hit_triangle(i, &ray, t_range, false)
+        });
+        match hit {
+            Some(hit) => area_to_solid_angle_pdf(
+                direction,
+                hit.t,
+                hit.geometric_normal,
+                1.0 / self.total_area(),
+            ),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vec3, sampler: &mut dyn Sampler) -> Vec3 {
+        // Pick a triangle with probability proportional to its area, then a point on it.
+        let target = sampler.get_1d() * self.total_area();
+        let index = self
+            .area_cdf
+            .partition_point(|&area| area < target)
+            .min(self.indices.len() - 1);
+
+        let [i0, i1, i2] = self.indices[index];
+        let (p0, p1, p2) = (self.positions[i0], self.positions[i1], self.positions[i2]);
+        sample_triangle(p0, p1, p2, sampler.get_2d()) - origin
+    }
+}
@@ -1,6 +1,5 @@
-use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, Mul, MulAssign, Neg, Sub, SubAssign};
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Vec3(pub f64, pub f64, pub f64);
@@ -113,42 +112,53 @@ impl Neg for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.0,
+            1 => &self.1,
+            2 => &self.2,
+            _ => panic!("Vec3 index out of bounds: {}", axis),
+        }
+    }
+}
+
 impl Vec3 {
     pub const ZERO: Self = Vec3(0.0, 0.0, 0.0);
     pub const ONE: Self = Vec3(1.0, 1.0, 1.0);
 
-    pub fn random_in_unit_sphere() -> Self {
-        let mut rng = rand::thread_rng();
-        loop {
-            let v = Vec3(
-                rng.gen::<f64>() * 2.0 - 1.0,
-                rng.gen::<f64>() * 2.0 - 1.0,
-                rng.gen::<f64>() * 2.0 - 1.0,
-            );
-            if v.mag_squared() <= 1.0 {
-                break v;
-            }
-        }
+    /// Maps a uniform sample of the unit square to a uniformly distributed point in the unit
+    /// sphere, using `w` for the distance from the center.
+    pub fn sample_in_unit_sphere(u: (f64, f64), w: f64) -> Self {
+        Vec3::sample_unit_vector(u) * w.cbrt()
     }
 
-    pub fn random_in_unit_disk() -> Self {
-        let mut rng = rand::thread_rng();
-        loop {
-            let v = Vec3(
-                rng.gen::<f64>() * 2.0 - 1.0,
-                rng.gen::<f64>() * 2.0 - 1.0,
-                0.0,
-            );
-            if v.mag_squared() <= 1.0 {
-                break v;
-            }
+    /// Maps a uniform sample of the unit square to a uniformly distributed point in the unit
+    /// disk in the xy plane, with Shirley and Chiu's concentric mapping, which keeps strata of
+    /// the square intact (unlike rejection sampling).
+    pub fn sample_in_unit_disk((u, v): (f64, f64)) -> Self {
+        let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+        if x == 0.0 && y == 0.0 {
+            return Vec3::ZERO;
         }
+
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, std::f64::consts::FRAC_PI_4 * (y / x))
+        } else {
+            (
+                y,
+                std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (x / y),
+            )
+        };
+        Vec3(r * theta.cos(), r * theta.sin(), 0.0)
     }
 
-    pub fn random_unit_vector() -> Self {
-        let mut rng = rand::thread_rng();
-        let a: f64 = rng.gen::<f64>() * std::f64::consts::PI * 2.0; // TODO: TAU when stable
-        let z = rng.gen::<f64>() * 2.0 - 1.0;
+    /// Maps a uniform sample of the unit square to a uniformly distributed direction.
+    pub fn sample_unit_vector((u, v): (f64, f64)) -> Self {
+        let a = u * std::f64::consts::PI * 2.0; // TODO: TAU when stable
+        let z = v * 2.0 - 1.0;
         let r = (1.0 - z * z).sqrt();
         Vec3(r * a.cos(), r * a.sin(), z)
     }
@@ -177,6 +187,36 @@ impl Vec3 {
         self / self.mag()
     }
 
+    pub fn min(self, rhs: Self) -> Self {
+        Vec3(self.0.min(rhs.0), self.1.min(rhs.1), self.2.min(rhs.2))
+    }
+
+    pub fn max(self, rhs: Self) -> Self {
+        Vec3(self.0.max(rhs.0), self.1.max(rhs.1), self.2.max(rhs.2))
+    }
+
+    /// Applies `f` to each component.
+    pub fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        Vec3(f(self.0), f(self.1), f(self.2))
+    }
+
+    /// The brightness of a linear RGB color, with the Rec. 709 weights.
+    pub fn luminance(self) -> f64 {
+        0.2126 * self.0 + 0.7152 * self.1 + 0.0722 * self.2
+    }
+
+    /// Two vectors that together with `self` (which has to be normalized) form an orthonormal
+    /// basis. From "Building an Orthonormal Basis, Revisited" (Duff et al., 2017).
+    pub fn orthonormal_basis(self) -> (Self, Self) {
+        let sign = 1f64.copysign(self.2);
+        let a = -1.0 / (sign + self.2);
+        let b = self.0 * self.1 * a;
+        (
+            Vec3(1.0 + sign * self.0 * self.0 * a, sign * b, -sign * self.0),
+            Vec3(b, sign + self.1 * self.1 * a, -self.1),
+        )
+    }
+
     pub fn reflect(self, normal: Self) -> Self {
         self - 2.0 * self.dot(normal) * normal
     }